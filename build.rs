@@ -1,28 +1,98 @@
 #[cfg(not(feature = "download"))]
 mod download {}
 
-#[cfg(any(not(feature = "download"), feature = "doc"))]
-fn main() {}
-
-#[cfg(all(feature = "download", not(feature = "doc")))]
 fn main() {
+    relay::build().unwrap();
+    #[cfg(all(feature = "download", not(feature = "doc")))]
     download::start().unwrap();
 }
 
+mod relay {
+    use anyhow::Context;
+    use std::env;
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    /// Compile the plugin relay executable, exposing its path as `LIGHTNINGD_RELAY`.
+    ///
+    /// The relay is launched by lightningd, so it's built for `TARGET`, linked with the linker
+    /// configured for it, if any. Documentation builds only need the env var, so nothing is
+    /// compiled there.
+    pub fn build() -> anyhow::Result<()> {
+        println!("cargo:rerun-if-changed=src/relay.rs");
+        let out_dir = PathBuf::from(env::var("OUT_DIR")?);
+        let relay = out_dir.join("lightningd-relay");
+        println!("cargo:rustc-env=LIGHTNINGD_RELAY={}", relay.display());
+        if env::var_os("CARGO_FEATURE_DOC").is_some() || env::var_os("DOCS_RS").is_some() {
+            return Ok(());
+        }
+
+        let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+        let mut command = Command::new(&rustc);
+        command
+            .args(["--edition", "2018", "-O", "--crate-name", "lightningd_relay", "-o"])
+            .arg(&relay)
+            .arg("src/relay.rs");
+        let (target, host) = (env::var("TARGET")?, env::var("HOST")?);
+        if target != host {
+            command.args(["--target", target.as_str()]);
+            if let Some(linker) = env::var_os("RUSTC_LINKER") {
+                command.arg("-C").arg(format!("linker={}", linker.to_string_lossy()));
+            }
+        }
+        let status = command
+            .status()
+            .with_context(|| format!("cannot run {}", rustc))?;
+        anyhow::ensure!(status.success(), "cannot compile the plugin relay: {}", status);
+        Ok(())
+    }
+}
+
 #[cfg(all(feature = "download", not(feature = "doc")))]
 mod download {
 
     use anyhow::Context;
     use bitcoin_hashes::{sha256, Hash};
     use flate2::read::GzDecoder;
+    use fs4::FileExt;
     use xz::read::XzDecoder;
-    use std::fs::File;
-    use std::io::{BufRead, BufReader, Read};
-    use std::path::Path;
+    use bitcoin_hashes::HashEngine;
+    use std::fs::{File, OpenOptions};
+    use std::io::{BufReader, Read, Write};
+    use std::path::{Path, PathBuf};
     use std::str::FromStr;
+    use std::thread;
+    use std::time::Duration;
     use tar::Archive;
 
-    include!("src/versions.rs");
+    #[allow(dead_code)]
+    mod versions {
+        include!("src/versions.rs");
+    }
+    use versions::Release;
+
+    mod signature {
+        include!("src/signature.rs");
+    }
+
+    const DEFAULT_ENDPOINT: &str = "https://github.com/ElementsProject/lightning/releases/download";
+
+    const MAX_ATTEMPTS: u32 = 5;
+
+    /// Env var disabling the signature verification of the `SHA256SUMS`, for endpoints serving
+    /// unsigned releases, like a local mirror of custom builds
+    const SKIP_SIGNATURE_ENV: &str = "LIGHTNINGD_SKIP_SIGNATURE_CHECK";
+
+    /// Release tarballs are around 20MB, bigger downloads are refused
+    const MAX_DOWNLOAD_SIZE: u64 = 256 * 1024 * 1024;
+
+    /// The newest release whose version feature is enabled
+    fn release() -> &'static Release {
+        versions::newest_enabled(|feature| {
+            std::env::var_os(format!("CARGO_FEATURE_{}", feature)).is_some()
+        })
+        .expect("the download feature must be enabled through a version feature like `23_05_2`")
+    }
 /*
     #[cfg(all(
         target_os = "macos",
@@ -51,67 +121,280 @@ mod download {
         format!("clightning-{}-win64.zip", &VERSION)
     }
 */
-    fn download_filename() -> String {
+    fn download_filename(release: &Release) -> String {
         let default = ubuntu_version::Version { major: 22, minor: 4, patch: 0 };
         let version = ubuntu_version::Version::detect().unwrap_or(default);
-        format!("clightning-{}-Ubuntu-{}.{:0>2}.tar.xz", &VERSION, version.major, version.minor)
-    }
-    fn get_expected_sha256(filename: &str) -> anyhow::Result<sha256::Hash> {
-        println!("{}",filename);
-        let sha256sums_filename = format!("sha256/clightning-{}-SHA256SUMS", &VERSION);
-        let sha256sums_filename = format!("{}", sha256sums_filename);
-        let file = File::open(&sha256sums_filename)
-            .with_context(|| format!("cannot find {:?}", sha256sums_filename))?;
-        for line in BufReader::new(file).lines().flatten() {
-            let tokens: Vec<_> = line.split("  ").collect();
-            if tokens.len() == 2 && filename == tokens[1] {
-                return Ok(sha256::Hash::from_str(tokens[0]).unwrap());
+        let detected = format!("{}.{:0>2}", version.major, version.minor);
+        let ubuntu = release
+            .ubuntu
+            .iter()
+            .find(|u| **u == detected)
+            .or_else(|| release.ubuntu.iter().find(|u| **u == "22.04"))
+            .or_else(|| release.ubuntu.last())
+            .unwrap();
+        release.asset(ubuntu)
+    }
+
+    /// Error which is not worth retrying, like a missing file on the endpoint
+    #[derive(Debug)]
+    struct Permanent(String);
+
+    impl std::fmt::Display for Permanent {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "{}", self.0)
+        }
+    }
+
+    impl std::error::Error for Permanent {}
+
+    /// Call `f` up to `MAX_ATTEMPTS` times with an exponential backoff, unless it fails with a
+    /// [Permanent] error
+    fn retry<T>(mut f: impl FnMut() -> anyhow::Result<T>) -> anyhow::Result<T> {
+        let mut attempt = 1;
+        loop {
+            match f() {
+                Err(e) if attempt < MAX_ATTEMPTS && e.downcast_ref::<Permanent>().is_none() => {
+                    let backoff = Duration::from_secs(1 << attempt);
+                    println!("attempt {} failed: {:#}, retrying in {:?}", attempt, e, backoff);
+                    thread::sleep(backoff);
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// GET request to `url` through the proxy specified with `HTTPS_PROXY`, if any
+    fn request(url: &str) -> anyhow::Result<minreq::Request> {
+        let request = minreq::get(url);
+        match std::env::var("HTTPS_PROXY").or_else(|_| std::env::var("https_proxy")) {
+            Ok(proxy) => {
+                let proxy = minreq::Proxy::new(&proxy)
+                    .with_context(|| format!("invalid HTTPS_PROXY {}", proxy))?;
+                Ok(request.with_proxy(proxy))
+            }
+            Err(_) => Ok(request),
+        }
+    }
+
+    fn check_status(url: &str, status_code: i32) -> anyhow::Result<()> {
+        match status_code {
+            200 | 206 => Ok(()),
+            408 | 429 | 500..=599 => Err(anyhow::anyhow!("url {} returned {}", url, status_code)),
+            _ => Err(Permanent(format!("url {} returned {}", url, status_code)).into()),
+        }
+    }
+
+    /// Stream `url` into the `partial` file, resuming from its current length if it exists, and
+    /// return the sha256 of the whole file
+    fn download(url: &str, partial: &Path) -> anyhow::Result<sha256::Hash> {
+        let mut engine = sha256::Hash::engine();
+        let mut len = match File::open(partial) {
+            Ok(f) => std::io::copy(&mut BufReader::new(f), &mut engine)?,
+            Err(_) => 0,
+        };
+
+        let mut request = request(url)?;
+        if len > 0 {
+            println!("resuming download of {} from byte {}", url, len);
+            request = request.with_header("Range", format!("bytes={}-", len));
+        }
+        let mut resp = request
+            .send_lazy()
+            .with_context(|| format!("cannot reach url {}", url))?;
+        if resp.status_code == 416 && len > 0 {
+            // nothing left past the end of the file, it's complete or longer than the asset,
+            // which the hash check tells apart
+            println!("{} is already fully downloaded", url);
+            return Ok(sha256::Hash::from_engine(engine));
+        }
+        check_status(url, resp.status_code)?;
+
+        let mut file = OpenOptions::new().create(true).append(true).open(partial)?;
+        if resp.status_code == 200 && len > 0 {
+            // the server doesn't support ranges, start over
+            file.set_len(0)?;
+            engine = sha256::Hash::engine();
+            len = 0;
+        }
+        let total = match resp.headers.get("content-length") {
+            Some(content_length) => Some(len + content_length.parse::<u64>()?),
+            None => None,
+        };
+        if let Some(total) = total.filter(|total| *total > MAX_DOWNLOAD_SIZE) {
+            return Err(Permanent(format!("{} is {} bytes, larger than the max allowed", url, total)).into());
+        }
+
+        let mut buffer = [0u8; 64 * 1024];
+        loop {
+            let read = resp.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            len += read as u64;
+            if len > MAX_DOWNLOAD_SIZE {
+                drop(file);
+                std::fs::remove_file(partial)?;
+                return Err(Permanent(format!("{} is larger than the max allowed", url)).into());
+            }
+            file.write_all(&buffer[..read])?;
+            engine.input(&buffer[..read]);
+        }
+        file.sync_all()?;
+        if let Some(total) = total.filter(|total| len < *total) {
+            anyhow::bail!("download of {} interrupted at byte {} of {}", url, len, total);
+        }
+        Ok(sha256::Hash::from_engine(engine))
+    }
+
+    /// Public keys of the lightning maintainers, bundled in the `keys` dir
+    fn maintainer_keys() -> anyhow::Result<Vec<pgp::SignedPublicKey>> {
+        let mut keys = vec![];
+        let dir = Path::new("keys");
+        if !dir.exists() {
+            return Ok(keys);
+        }
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "asc") {
+                let armored = std::fs::read_to_string(&path)?;
+                let parsed = signature::parse_keys(&armored)
+                    .with_context(|| format!("invalid key in {:?}", path))?;
+                keys.extend(parsed);
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Content of the `SHA256SUMS` of the release, bundled in the `sha256` dir.
+    ///
+    /// When its signature is bundled too, the `SHA256SUMS` must be signed by one of the bundled
+    /// maintainer keys, unless [SKIP_SIGNATURE_ENV] is set. Without a bundled signature the
+    /// committed `SHA256SUMS` is trusted as is, with a warning.
+    fn sha256sums(release: &Release) -> anyhow::Result<String> {
+        let bundled = Path::new("sha256").join(release.sha256sums_filename());
+        let sha256sums = std::fs::read_to_string(&bundled)
+            .with_context(|| format!("cannot read {:?}", bundled))?;
+        let signatures = match std::fs::read_to_string(format!("{}.asc", bundled.display())) {
+            Ok(signatures) => signatures,
+            Err(_) => {
+                println!(
+                    "cargo:warning={} has no bundled signature, its hashes are not verified",
+                    release.sha256sums_filename()
+                );
+                return Ok(sha256sums);
+            }
+        };
+
+        if std::env::var_os(SKIP_SIGNATURE_ENV).is_some() {
+            println!(
+                "cargo:warning={} is set, skipping signature verification of {}",
+                SKIP_SIGNATURE_ENV,
+                release.sha256sums_filename()
+            );
+            return Ok(sha256sums);
+        }
+        let keys = maintainer_keys()?;
+        anyhow::ensure!(
+            !keys.is_empty(),
+            "no maintainer keys in the keys dir to verify {}, set {} to skip the verification",
+            release.sha256sums_filename(),
+            SKIP_SIGNATURE_ENV
+        );
+        let signer = signature::verify(sha256sums.as_bytes(), &signatures, &keys)
+            .with_context(|| format!("invalid signature of {}", release.sha256sums_filename()))?;
+        println!("{} signed by {}", release.sha256sums_filename(), signer);
+        Ok(sha256sums)
+    }
+
+    fn get_expected_sha256(release: &Release, filename: &str) -> anyhow::Result<sha256::Hash> {
+        let sha256sums = sha256sums(release)?;
+        for (hash, name) in versions::parse_sha256sums(&sha256sums) {
+            if filename == name {
+                return Ok(sha256::Hash::from_str(hash)?);
             }
         }
         panic!(
             "Couldn't find hash for `{}` in `{}`:\n{}",
             filename,
-            sha256sums_filename,
-            std::fs::read_to_string(&sha256sums_filename).unwrap()
+            release.sha256sums_filename(),
+            sha256sums
         );
     }
 
+    /// Extract the tarball into `home` through a staging dir, so that the tree is moved in place
+    /// only once complete
+    fn extract(tarball_path: &Path, filename: &str, home: &Path) -> anyhow::Result<()> {
+        let staging = home.with_file_name("lightning.staging");
+        if staging.exists() {
+            std::fs::remove_dir_all(&staging)?;
+        }
+        let tarball = BufReader::new(File::open(tarball_path)?);
+        if filename.ends_with(".tar.gz") {
+            Archive::new(GzDecoder::new(tarball)).unpack(&staging)?;
+        } else if filename.ends_with(".tar.xz") {
+            Archive::new(XzDecoder::new(tarball)).unpack(&staging)?;
+        }
+        anyhow::ensure!(
+            staging.join("usr").join("bin").join("lightningd").exists(),
+            "lightningd not found in the tarball"
+        );
+        if home.exists() {
+            // leftover of an incomplete extraction
+            std::fs::remove_dir_all(home)?;
+        }
+        std::fs::rename(&staging, home)
+            .with_context(|| format!("cannot move {:?} to {:?}", staging, home))?;
+        Ok(())
+    }
+
     pub(crate) fn start() -> anyhow::Result<()> {
-        let download_filename = download_filename();
-        let expected_hash = get_expected_sha256(&download_filename)?;
+        // the relay emits rerun-if-changed, so the bundled files must be listed too
+        println!("cargo:rerun-if-changed=sha256");
+        println!("cargo:rerun-if-changed=keys");
+        println!("cargo:rerun-if-env-changed={}", SKIP_SIGNATURE_ENV);
+        println!("cargo:rerun-if-env-changed=LIGHTNINGD_DOWNLOAD_ENDPOINT");
+        println!("cargo:rerun-if-env-changed=LIGHTNINGD_TARBALL_FILE");
+        let release = release();
+        let download_endpoint = std::env::var("LIGHTNINGD_DOWNLOAD_ENDPOINT")
+            .unwrap_or_else(|_| DEFAULT_ENDPOINT.to_owned());
+        let download_filename = download_filename(release);
         let out_dir = std::env::var_os("OUT_DIR").unwrap();
 
         let lightning_exe_home = Path::new(&out_dir).join("lightning");
-        if !lightning_exe_home.exists() {
-            std::fs::create_dir(&lightning_exe_home)
-                .with_context(|| format!("cannot create dir {:?}", lightning_exe_home))?;
-        }
         let existing_filename = lightning_exe_home
             .join("usr")
             .join("bin")
             .join("lightningd");
 
-        println!(
-            "filename:{} version:{} hash:{}",
-            download_filename, VERSION, expected_hash
-        );
+        // concurrent builds sharing the same `OUT_DIR` wait here for the first one to be done
+        let lock = File::create(Path::new(&out_dir).join("lightning.lock"))?;
+        FileExt::lock_exclusive(&lock).with_context(|| "cannot lock lightning.lock")?;
 
         if !existing_filename.exists() {
+            let expected_hash = get_expected_sha256(release, &download_filename)?;
+            println!(
+                "filename:{} version:{} hash:{}",
+                download_filename, release.version, expected_hash
+            );
 
-            let (file_or_url, tarball_bytes) = match std::env::var("LIGHTNINGD_TARBALL_FILE") {
+            let (file_or_url, tarball_path, tarball_hash) = match std::env::var("LIGHTNINGD_TARBALL_FILE") {
                 Err(_) => {
-                    let download_endpoint = std::env::var("LIGHTNINGD_TARBALL_FILE")
-                        .unwrap_or("https://github.com/ElementsProject/lightning/releases/download/".to_owned());
                     let url = format!(
                         "{}/{}/{}",
-                        download_endpoint, VERSION, download_filename
+                        download_endpoint, release.version, download_filename
                     );
-                    let resp = minreq::get(&url)
-                        .send()
-                        .with_context(|| format!("cannot reach url {}", url))?;
-                    assert_eq!(resp.status_code, 200, "url {} didn't return 200", url);
-
-                    (url, resp.as_bytes().to_vec())
+                    let partial = Path::new(&out_dir).join(format!("{}.part", download_filename));
+                    let mut tarball_hash = retry(|| download(&url, &partial))?;
+                    if tarball_hash != expected_hash {
+                        // a corrupted partial download must not be resumed, start over once
+                        std::fs::remove_file(&partial)?;
+                        tarball_hash = retry(|| download(&url, &partial))?;
+                        if tarball_hash != expected_hash {
+                            std::fs::remove_file(&partial)?;
+                        }
+                    }
+                    (url, partial, tarball_hash)
                 }
                 Ok(path) => {
                     let f = File::open(&path).with_context(|| {
@@ -120,43 +403,30 @@ mod download {
                             &path
                         )
                     })?;
-                    let mut reader = BufReader::new(f);
-                    let mut buffer = Vec::new();
-                    reader.read_to_end(&mut buffer)?;
-                    (path, buffer)
+                    let mut engine = sha256::Hash::engine();
+                    std::io::copy(&mut BufReader::new(f), &mut engine)?;
+                    (path.clone(), PathBuf::from(path), sha256::Hash::from_engine(engine))
                 }
             };
 
-            let tarball_hash = sha256::Hash::hash(&tarball_bytes);
             assert_eq!(
                 expected_hash, tarball_hash,
                 "expected hash of {} is not matching",
                 file_or_url
             );
 
-            if download_filename.ends_with(".tar.gz") {
-                let d = GzDecoder::new(&tarball_bytes[..]);
-
-                let mut archive = Archive::new(d);
-                for mut entry in archive.entries().unwrap().flatten() {
-                    if let Ok(file) = entry.path() {
-                        if file.ends_with("lightningd") {
-                            entry.unpack_in(&lightning_exe_home).unwrap();
-                        }
-                    }
-                }
-            } else if download_filename.ends_with(".tar.xz") {
-                let d = XzDecoder::new(&tarball_bytes[..]);
-                let mut archive = Archive::new(d);
-                for mut entry in archive.entries().unwrap().flatten() {
-                    if let Ok(file) = entry.path() {
-                        if file.ends_with("lightningd") {
-                            entry.unpack_in(&lightning_exe_home).unwrap();
-                        }
-                    }
-                }
+            let extracted = extract(&tarball_path, &download_filename, &lightning_exe_home)
+                .with_context(|| format!("cannot extract {}", file_or_url));
+            if extracted.is_err() && std::env::var("LIGHTNINGD_TARBALL_FILE").is_err() {
+                // the next build must download it again
+                let _ = std::fs::remove_file(&tarball_path);
+            }
+            extracted?;
+            if std::env::var("LIGHTNINGD_TARBALL_FILE").is_err() {
+                std::fs::remove_file(&tarball_path)?;
             }
         }
+        FileExt::unlock(&lock)?;
         Ok(())
     }
-}
\ No newline at end of file
+}
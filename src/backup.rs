@@ -0,0 +1,228 @@
+use crate::{new_work_dir, Conf, LightningD};
+use anyhow::Context;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+#[cfg(not(feature = "db"))]
+use std::process::Command;
+
+/// Name of the default sqlite wallet in the network dir
+pub(crate) const WALLET_FILE: &str = "lightningd.sqlite3";
+
+/// Name of the replica of the wallet in the work dir, if [Conf::wallet_backup] is set
+pub(crate) const BACKUP_FILE: &str = "lightningd-backup.sqlite3";
+
+/// The `--wallet` value replicating the default wallet in `network_dir` to `backup`
+pub(crate) fn replicated_dsn(network_dir: &Path, backup: &Path) -> String {
+    format!(
+        "sqlite3://{}:{}",
+        network_dir.join(WALLET_FILE).display(),
+        backup.display()
+    )
+}
+
+/// The content of the sqlite database at `path`, a line for the schema of every table followed by
+/// a line for each of its rows
+#[cfg(feature = "db")]
+fn dump(path: &Path) -> anyhow::Result<String> {
+    use rusqlite::types::ValueRef;
+    use rusqlite::{Connection, OpenFlags};
+
+    let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+    let connection = Connection::open_with_flags(path, flags)
+        .with_context(|| format!("cannot open {}", path.display()))?;
+    let tables: Vec<(String, String)> = connection
+        .prepare("SELECT name, sql FROM sqlite_master WHERE type = 'table' ORDER BY name")?
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+    let mut lines = vec![];
+    for (table, sql) in tables {
+        lines.push(sql);
+        let mut statement =
+            connection.prepare(&format!("SELECT * FROM \"{}\"", table.replace('"', "\"\"")))?;
+        let columns = statement.column_count();
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let mut values = vec![];
+            for i in 0..columns {
+                values.push(match row.get_ref(i)? {
+                    ValueRef::Null => "NULL".to_string(),
+                    ValueRef::Integer(i) => i.to_string(),
+                    ValueRef::Real(f) => f.to_string(),
+                    ValueRef::Text(text) => format!("'{}'", String::from_utf8_lossy(text)),
+                    ValueRef::Blob(blob) => {
+                        let hex: String = blob.iter().map(|b| format!("{:02x}", b)).collect();
+                        format!("X'{}'", hex)
+                    }
+                });
+            }
+            lines.push(format!("{}({})", table, values.join(",")));
+        }
+    }
+    Ok(lines.join("\n"))
+}
+
+/// The content of the sqlite database at `path` as sql statements, from the `sqlite3` executable
+#[cfg(not(feature = "db"))]
+fn dump(path: &Path) -> anyhow::Result<String> {
+    let sqlite3 = which::which("sqlite3").context(
+        "`sqlite3` not found in the PATH, install it or enable the `db` feature to compare the \
+         wallets without it",
+    )?;
+    let output = Command::new(sqlite3)
+        .arg("-readonly")
+        .arg(path)
+        .arg(".dump")
+        .output()?;
+    anyhow::ensure!(
+        output.status.success(),
+        "cannot dump {}: {}",
+        path.display(),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// Methods requiring a node started with [Conf::wallet_backup]
+impl LightningD {
+    /// Return the path of the replica of the wallet
+    pub fn wallet_backup_path(&self) -> Option<&Path> {
+        self.wallet_backup.as_deref()
+    }
+
+    /// Check the replica of the wallet contains the same data of the wallet, failing with the
+    /// first difference.
+    ///
+    /// Call it when the node is idle, after the workload, since the databases are compared while
+    /// the node is running. Without the `db` feature, requires the `sqlite3` executable in the
+    /// `PATH`.
+    pub fn verify_wallet_backup(&self) -> anyhow::Result<()> {
+        let backup = self
+            .wallet_backup_path()
+            .ok_or_else(|| anyhow::anyhow!("verify_wallet_backup requires Conf::wallet_backup"))?;
+        let wallet = self.network_dir()?.join(WALLET_FILE);
+        let (wallet_dump, backup_dump) = (dump(&wallet)?, dump(backup)?);
+        let mut lines = wallet_dump.lines().zip(backup_dump.lines());
+        if let Some((w, b)) = lines.find(|(w, b)| w != b) {
+            anyhow::bail!("backup differs from the wallet: `{}` instead of `{}`", b, w);
+        }
+        anyhow::ensure!(
+            wallet_dump.lines().count() == backup_dump.lines().count(),
+            "backup has {} lines of dump instead of {}",
+            backup_dump.lines().count(),
+            wallet_dump.lines().count()
+        );
+        Ok(())
+    }
+
+    /// Launch a new node with `conf` restored from the replica of the wallet of this node and its
+    /// `hsm_secret`, like after losing the main disk.
+    ///
+    /// Stop this node first, the restored node has the same id and channels. The restored node
+    /// is not launched again if it exits early, ignoring [Conf::attempts].
+    pub fn restore_wallet_backup<S: AsRef<OsStr>>(
+        &self,
+        exe: S,
+        conf: &Conf,
+    ) -> anyhow::Result<LightningD> {
+        let backup = self
+            .wallet_backup_path()
+            .ok_or_else(|| anyhow::anyhow!("restore_wallet_backup requires Conf::wallet_backup"))?;
+        let work_dir = new_work_dir(conf)?;
+        let network_dir = work_dir.path().join(conf.network);
+        fs::create_dir_all(&network_dir)?;
+        fs::copy(backup, network_dir.join(WALLET_FILE))
+            .with_context(|| format!("cannot copy {}", backup.display()))?;
+        fs::copy(
+            self.network_dir()?.join("hsm_secret"),
+            network_dir.join("hsm_secret"),
+        )?;
+        let mut conf = conf.clone();
+        conf.attempts = 0;
+        LightningD::with_work_dir(exe, &conf, work_dir)
+    }
+
+    pub(crate) fn network_dir(&self) -> anyhow::Result<PathBuf> {
+        self.rpc_path
+            .parent()
+            .map(Path::to_path_buf)
+            .ok_or_else(|| anyhow::anyhow!("no network dir in {}", self.rpc_path.display()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::replicated_dsn;
+    use crate::{exe_path, Conf, LightningD};
+    use std::path::Path;
+
+    #[test]
+    fn test_replicated_dsn() {
+        assert_eq!(
+            replicated_dsn(Path::new("/w/regtest"), Path::new("/w/backup.sqlite3")),
+            "sqlite3:///w/regtest/lightningd.sqlite3:/w/backup.sqlite3"
+        );
+    }
+
+    #[cfg(feature = "db")]
+    #[test]
+    fn test_dump() {
+        use super::dump;
+        use rusqlite::{params, Connection};
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let create = |name: &str, label: &str| {
+            let path = dir.path().join(name);
+            let connection = Connection::open(&path).unwrap();
+            connection
+                .execute_batch(
+                    "CREATE TABLE invoices (id INTEGER PRIMARY KEY, label TEXT, hash BLOB, \
+                     msat INTEGER, expiry REAL);",
+                )
+                .unwrap();
+            connection
+                .execute(
+                    "INSERT INTO invoices VALUES (1, ?1, ?2, NULL, 1.5)",
+                    params![label, vec![0xabu8, 0x01]],
+                )
+                .unwrap();
+            path
+        };
+        let (wallet, backup, other) = (
+            create("wallet", "l"),
+            create("backup", "l"),
+            create("other", "m"),
+        );
+
+        let dumped = dump(&wallet).unwrap();
+        assert_eq!(
+            dumped.lines().nth(1).unwrap(),
+            "invoices(1,'l',X'ab01',NULL,1.5)"
+        );
+        assert_eq!(dumped, dump(&backup).unwrap());
+        assert_ne!(dumped, dump(&other).unwrap());
+    }
+
+    #[test]
+    fn test_wallet_backup() {
+        let _ = env_logger::try_init();
+        let conf = Conf {
+            wallet_backup: true,
+            ..Default::default()
+        };
+        let mut lightningd = LightningD::with_conf(exe_path().unwrap(), &conf).unwrap();
+        let invoice = lightningd.create_invoice(1000, "backup").unwrap();
+        lightningd.verify_wallet_backup().unwrap();
+
+        let id = lightningd.node_id().unwrap();
+        lightningd.stop().unwrap();
+        let restored = lightningd
+            .restore_wallet_backup(exe_path().unwrap(), &Conf::default())
+            .unwrap();
+        assert_eq!(restored.node_id().unwrap(), id);
+        let invoices = restored
+            .call("listinvoices", serde_json::json!([invoice.label]))
+            .unwrap();
+        assert_eq!(invoices["invoices"].as_array().unwrap().len(), 1);
+    }
+}
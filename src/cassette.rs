@@ -0,0 +1,361 @@
+//! Record and replay of json-rpc traffic.
+//!
+//! A cassette is a JSON-lines file, each line is a request with its response like
+//! `{"method":"getinfo","params":[],"result":{...}}` or
+//! `{"method":"pay","params":["lnbcrt..."],"error":{"code":210,"message":"..."}}`.
+
+use crate::payment::is_generated_label;
+use crate::server::RpcServer;
+use crate::RpcError;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Deserializer, Value};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Json-rpc error code returned when a request doesn't match the cassette or can't be forwarded
+const UNEXPECTED_REQUEST: i64 = -32600;
+
+/// A line of a cassette
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Interaction {
+    method: String,
+    params: Value,
+    #[serde(flatten)]
+    response: Response,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Response {
+    Result(Value),
+    Error(RpcError),
+}
+
+/// Start a proxy forwarding every request to the node listening on `target` and appending the
+/// interactions to `cassette`, truncated if existing
+pub(crate) fn record(target: PathBuf, cassette: &Path) -> anyhow::Result<RpcServer> {
+    let file = Mutex::new(File::create(cassette)?);
+    RpcServer::start(
+        "lightning-rpc",
+        Arc::new(move |method, params| {
+            let response = forward(&target, method, params).map_err(|e| {
+                RpcError::new(
+                    UNEXPECTED_REQUEST,
+                    &format!("cannot forward to lightningd: {}", e),
+                )
+            })?;
+            let interaction = Interaction {
+                method: method.to_string(),
+                params: params.clone(),
+                response,
+            };
+            let line = serde_json::to_string(&interaction).expect("serializable");
+            let mut file = file.lock().unwrap();
+            if let Err(e) = writeln!(file, "{}", line).and_then(|_| file.flush()) {
+                log::warn!("cannot write to cassette: {:?}", e);
+            }
+            interaction.response.into()
+        }),
+    )
+}
+
+/// Send the request to `target` on a new connection, like [clightningrpc] does
+fn forward(target: &Path, method: &str, params: &Value) -> anyhow::Result<Response> {
+    let stream = UnixStream::connect(target)?;
+    let request = json!({"jsonrpc": "2.0", "id": 0, "method": method, "params": params});
+    serde_json::to_writer(&stream, &request)?;
+    let mut response: Value = Deserializer::from_reader(&stream)
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("connection closed"))??;
+    Ok(match response.get_mut("error") {
+        Some(error) if !error.is_null() => Response::Error(serde_json::from_value(error.take())?),
+        _ => Response::Result(response["result"].take()),
+    })
+}
+
+/// Interactions of a cassette yet to be served by a replaying mock
+#[derive(Debug)]
+pub(crate) struct Replay {
+    pending: VecDeque<Interaction>,
+    /// The interaction served last, served again to the requests repeating it
+    last: Option<Interaction>,
+    unexpected: Vec<String>,
+}
+
+impl Replay {
+    pub(crate) fn load(cassette: &Path) -> anyhow::Result<Replay> {
+        let content = fs::read_to_string(cassette)?;
+        let mut pending = VecDeque::new();
+        for (i, line) in content.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let interaction: Interaction = serde_json::from_str(line).map_err(|e| {
+                anyhow::anyhow!("invalid line {} of {}: {}", i + 1, cassette.display(), e)
+            })?;
+            // polling loops repeat a request a different number of times on every run, only the
+            // response ending the loop is kept
+            if pending
+                .back()
+                .is_some_and(|last: &Interaction| last.is_repeated_by(&interaction))
+            {
+                pending.pop_back();
+            }
+            pending.push_back(interaction);
+        }
+        Ok(Replay {
+            pending,
+            last: None,
+            unexpected: vec![],
+        })
+    }
+
+    pub(crate) fn handle(&mut self, method: &str, params: &Value) -> Result<Value, RpcError> {
+        if self
+            .pending
+            .front()
+            .is_some_and(|next| next.matches(method, params))
+        {
+            let next = self.pending.pop_front().expect("checked");
+            self.last = Some(next.clone());
+            return next.response.into();
+        }
+        if let Some(last) = self
+            .last
+            .as_ref()
+            .filter(|last| last.matches(method, params))
+        {
+            return last.response.clone().into();
+        }
+        let expected = match self.pending.front() {
+            Some(next) => format!("`{}` {}", next.method, next.params),
+            None => "the end of the cassette".to_string(),
+        };
+        let message = format!(
+            "unexpected request `{}` {}, expecting {}",
+            method, params, expected
+        );
+        self.unexpected.push(message.clone());
+        Err(RpcError::new(UNEXPECTED_REQUEST, &message))
+    }
+
+    pub(crate) fn check(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(self.unexpected.is_empty(), self.unexpected.join("\n"));
+        anyhow::ensure!(
+            self.pending.is_empty(),
+            "{} interactions not replayed, starting with `{}`",
+            self.pending.len(),
+            self.pending[0].method
+        );
+        Ok(())
+    }
+}
+
+impl Interaction {
+    /// Whether a request matches this interaction, ignoring the labels generated by
+    /// [crate::LightningD::create_invoice], which are unique to every run
+    fn matches(&self, method: &str, params: &Value) -> bool {
+        self.method == method && without_labels(&self.params) == without_labels(params)
+    }
+
+    /// Whether `next` repeats the request of this interaction, like a polling loop does
+    fn is_repeated_by(&self, next: &Interaction) -> bool {
+        self.method == next.method && self.params == next.params
+    }
+}
+
+impl From<Response> for Result<Value, RpcError> {
+    fn from(response: Response) -> Self {
+        match response {
+            Response::Result(result) => Ok(result),
+            Response::Error(error) => Err(error),
+        }
+    }
+}
+
+/// `params` with the generated labels replaced by `null`, wherever they are
+fn without_labels(params: &Value) -> Value {
+    match params {
+        Value::String(s) if is_generated_label(s) => Value::Null,
+        Value::Array(values) => Value::Array(values.iter().map(without_labels).collect()),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(k, v)| (k.clone(), without_labels(v)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::record;
+    use crate::mock::default_getinfo;
+    use crate::{exe_path, wait_for_blockheight, Conf, LightningD, MockLightningD, RpcError};
+    use clightningrpc::LightningRPC;
+    use serde_json::{json, Value};
+    use std::time::Duration;
+
+    #[test]
+    fn test_record_replay() {
+        let node = MockLightningD::new().unwrap();
+        node.respond_with("echo", |params| Ok(params.clone()));
+        node.respond_error("pay", 210, "no route");
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let cassette = dir.path().join("cassette.jsonl");
+        {
+            let recorder = record(node.rpc_path(), &cassette).unwrap();
+            let client = LightningRPC::new(recorder.rpc_path());
+            assert_eq!(client.getinfo().unwrap().alias, "MOCK");
+            let echo: Value = client.call("echo", json!({"a": [1]})).unwrap();
+            assert_eq!(echo, json!({"a": [1]}));
+            assert!(client.call::<_, Value>("pay", json!(["lnbcrt1"])).is_err());
+        }
+        let content = std::fs::read_to_string(&cassette).unwrap();
+        assert_eq!(content.lines().count(), 3);
+        assert!(content.contains(r#""error":{"code":210,"message":"no route"}"#));
+
+        let replay = MockLightningD::replay(&cassette).unwrap();
+        let client = replay.client();
+        assert_eq!(client.getinfo().unwrap().alias, "MOCK");
+        assert!(replay.check_replayed().is_err());
+        let echo: Value = client.call("echo", json!({"a": [1]})).unwrap();
+        assert_eq!(echo, json!({"a": [1]}));
+        replay.check_replayed().unwrap_err();
+        assert!(client.call::<_, Value>("pay", json!(["lnbcrt1"])).is_err());
+        replay.check_replayed().unwrap();
+
+        // requests not in the cassette fail, as well as the replay
+        let replay = MockLightningD::replay(&cassette).unwrap();
+        let client = replay.client();
+        assert!(client.call::<_, Value>("echo", json!({"a": [1]})).is_err());
+        assert!(client.getinfo().is_ok());
+        let err = replay.check_replayed().unwrap_err().to_string();
+        assert!(err.contains("unexpected request `echo`"), "{}", err);
+
+        assert_eq!(
+            serde_json::from_value::<RpcError>(json!({"code": -1, "message": "m", "data": {}}))
+                .unwrap()
+                .data,
+            Some(json!({}))
+        );
+    }
+
+    #[test]
+    fn test_replay_ignores_labels() {
+        let node = MockLightningD::new().unwrap();
+        node.respond_with("invoice", |params| Ok(json!({ "label": params[1] })));
+        node.respond("listinvoices", json!({"invoices": []}));
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let cassette = dir.path().join("cassette.jsonl");
+        {
+            let recorder = record(node.rpc_path(), &cassette).unwrap();
+            let client = LightningRPC::new(recorder.rpc_path());
+            let _: Value = client
+                .call("invoice", json!([1000, "lightningd-1-0", "d"]))
+                .unwrap();
+            let _: Value = client
+                .call("listinvoices", json!({"label": "lightningd-1-0"}))
+                .unwrap();
+            let _: Value = client.call("listinvoices", json!(["fixed"])).unwrap();
+        }
+
+        let replay = MockLightningD::replay(&cassette).unwrap();
+        let client = replay.client();
+        let invoice: Value = client
+            .call("invoice", json!([1000, "lightningd-2-1", "d"]))
+            .unwrap();
+        assert_eq!(invoice["label"], "lightningd-1-0");
+        assert!(client
+            .call::<_, Value>("listinvoices", json!({"label": "lightningd-2-1"}))
+            .is_ok());
+        assert!(client
+            .call::<_, Value>("listinvoices", json!(["fixed"]))
+            .is_ok());
+        replay.check_replayed().unwrap();
+
+        // the other params and the labels chosen by the test must still match
+        let replay = MockLightningD::replay(&cassette).unwrap();
+        let client = replay.client();
+        assert!(client
+            .call::<_, Value>("invoice", json!([1001, "lightningd-1-0", "d"]))
+            .is_err());
+        assert!(replay.check_replayed().is_err());
+        let replay = MockLightningD::replay(&cassette).unwrap();
+        let client = replay.client();
+        let _: Value = client
+            .call("invoice", json!([1000, "lightningd-2-1", "d"]))
+            .unwrap();
+        let _: Value = client
+            .call("listinvoices", json!({"label": "lightningd-2-1"}))
+            .unwrap();
+        assert!(client
+            .call::<_, Value>("listinvoices", json!(["other"]))
+            .is_err());
+    }
+
+    #[test]
+    fn test_replay_polling() {
+        let node = MockLightningD::new().unwrap();
+        let mut height = 100;
+        node.respond_with("getinfo", move |_| {
+            height += 1;
+            let mut info = default_getinfo();
+            info["blockheight"] = json!(height);
+            Ok(info)
+        });
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let cassette = dir.path().join("cassette.jsonl");
+        {
+            let recorder = record(node.rpc_path(), &cassette).unwrap();
+            let client = LightningRPC::new(recorder.rpc_path());
+            wait_for_blockheight(&client, 103, Duration::from_secs(10)).unwrap();
+            assert!(client.call::<_, Value>("echo", json!([])).is_err());
+        }
+        let content = std::fs::read_to_string(&cassette).unwrap();
+        assert_eq!(content.lines().count(), 4);
+
+        // polling as many times as recorded, fewer or more, replays
+        for polls in [1, 3, 5].iter() {
+            let replay = MockLightningD::replay(&cassette).unwrap();
+            let client = replay.client();
+            for _ in 0..*polls {
+                assert_eq!(client.getinfo().unwrap().blockheight, 103);
+            }
+            assert!(client.call::<_, Value>("echo", json!([])).is_err());
+            replay.check_replayed().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_record_replay_invoice() {
+        let _ = env_logger::try_init();
+        let dir = tempfile::TempDir::new().unwrap();
+        let cassette = dir.path().join("cassette.jsonl");
+        let conf = Conf {
+            record: Some(cassette.clone()),
+            ..Default::default()
+        };
+        let invoice = {
+            let lightningd = LightningD::with_conf(exe_path().unwrap(), &conf).unwrap();
+            lightningd.create_invoice(1000, "replayed").unwrap()
+        };
+
+        // replaying the same code creates another label
+        let replay = MockLightningD::replay(&cassette).unwrap();
+        let result: Value = replay
+            .client()
+            .call("invoice", json!([1000, "lightningd-1-0", "replayed"]))
+            .unwrap();
+        assert_eq!(result["bolt11"], invoice.bolt11);
+        replay.check_replayed().unwrap();
+    }
+}
@@ -0,0 +1,890 @@
+use crate::plugin::{param, Plugin};
+use crate::{poll_until, LightningD, RpcError, Version, SYNC_TIMEOUT};
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::blockdata::opcodes::all::OP_PUSHNUM_1;
+use bitcoin::blockdata::script::Builder;
+use bitcoin::consensus::encode::{deserialize_hex, serialize_hex};
+use bitcoin::hashes::Hash;
+use bitcoin::{
+    absolute, block, transaction, Address, Amount, Block, BlockHash, Network, OutPoint, Script,
+    ScriptBuf, Sequence, Transaction, TxIn, TxMerkleNode, TxOut, Txid, Witness,
+};
+use clightningrpc::LightningRPC;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::PathBuf;
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Name of the plugin registered as bitcoin backend
+const PLUGIN_NAME: &str = "fake-chain";
+
+/// Default fee rate returned by `estimatefees`, in satoshi per kilo virtual byte
+const DEFAULT_FEERATE: u64 = 25_000;
+
+/// Minimum fee rate accepted by bitcoind relay policy, in satoshi per kilo virtual byte
+const FEERATE_FLOOR: u64 = 1_000;
+
+/// Block subsidy of every coinbase, halvings are ignored
+const SUBSIDY: Amount = Amount::from_sat(50 * 100_000_000);
+
+/// First version enabling the dev options with `--developer`, older ones only in developer builds
+const DEVELOPER_VERSION: Version = Version::new(23, 11, 0);
+
+static NODE_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// An in-memory regtest chain controlled by the test, served to lightningd by a bitcoin backend
+/// plugin in place of bitcoind and the `bcli` plugin.
+///
+/// The chain is shared by all its clones, so it could be passed to [crate::Conf::fake_chain] of
+/// multiple nodes and still be driven by the test.
+///
+/// ```no_run
+/// let chain = lightningd::FakeChain::new();
+/// let mut conf = lightningd::Conf::default();
+/// conf.fake_chain = Some(chain.clone());
+/// let node = lightningd::LightningD::with_conf(lightningd::exe_path().unwrap(), &conf).unwrap();
+/// chain.generate(10).unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct FakeChain {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug)]
+struct State {
+    blocks: Vec<Block>,
+    mempool: Vec<Transaction>,
+    broadcasts: Vec<Transaction>,
+    feerate: u64,
+    reject: Option<String>,
+    /// Blocks mined so far, making every coinbase unique even at the same height
+    mined: u64,
+    /// Transactions created by [FakeChain::send], making their fake inputs unique
+    sent: u64,
+    /// Sync state of the nodes served by this chain, by plugin
+    nodes: HashMap<u64, NodeSync>,
+}
+
+/// What a node fetched from the chain
+#[derive(Debug, Default)]
+struct NodeSync {
+    /// Height and hash of the last block returned to the node
+    last_block: Option<(u32, BlockHash)>,
+    /// The node asked for the block after the tip, once it processed the tip
+    at_tip: bool,
+    /// Rpc socket of the node, known once it's started
+    rpc_path: Option<PathBuf>,
+}
+
+/// The bitcoin backend plugin of a node, detaching the node from the chain when dropped
+#[derive(Debug)]
+pub(crate) struct Backend {
+    plugin: Plugin,
+    node: u64,
+    state: Arc<Mutex<State>>,
+}
+
+impl Backend {
+    pub(crate) fn plugin(&self) -> &Plugin {
+        &self.plugin
+    }
+
+    /// Set the rpc socket of the started node, to wait for it in [FakeChain::generate]
+    pub(crate) fn set_rpc_path(&self, rpc_path: PathBuf) {
+        if let Some(node) = self.state.lock().unwrap().nodes.get_mut(&self.node) {
+            node.rpc_path = Some(rpc_path);
+        }
+    }
+}
+
+impl Drop for Backend {
+    fn drop(&mut self) {
+        self.state.lock().unwrap().nodes.remove(&self.node);
+    }
+}
+
+impl PartialEq for FakeChain {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.state, &other.state)
+    }
+}
+
+impl Eq for FakeChain {}
+
+impl Default for FakeChain {
+    fn default() -> Self {
+        FakeChain::new()
+    }
+}
+
+impl FakeChain {
+    /// A chain containing only the regtest genesis block
+    pub fn new() -> FakeChain {
+        let state = State {
+            blocks: vec![genesis_block(Network::Regtest)],
+            mempool: vec![],
+            broadcasts: vec![],
+            feerate: DEFAULT_FEERATE,
+            reject: None,
+            mined: 0,
+            sent: 0,
+            nodes: HashMap::new(),
+        };
+        FakeChain {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Height of the tip
+    pub fn height(&self) -> u32 {
+        self.state.lock().unwrap().height()
+    }
+
+    /// The block at `height`, if any
+    pub fn block(&self, height: u32) -> Option<Block> {
+        self.state
+            .lock()
+            .unwrap()
+            .blocks
+            .get(height as usize)
+            .cloned()
+    }
+
+    /// Mine `n` blocks, the first one confirming all the transactions in the mempool, returning
+    /// their hashes without waiting for the nodes
+    pub fn mine(&self, n: u32) -> Vec<BlockHash> {
+        self.state.lock().unwrap().mine(n, &anyone_can_spend())
+    }
+
+    /// Mine `n` blocks like [FakeChain::mine], then wait until every node served by this chain
+    /// reports the new height in `getinfo`
+    pub fn generate(&self, n: u32) -> anyhow::Result<Vec<BlockHash>> {
+        let hashes = self.mine(n);
+        self.wait_blockheight()?;
+        Ok(hashes)
+    }
+
+    /// Mine `n` blocks whose coinbase pays to a new address of `node`, then wait like
+    /// [FakeChain::generate]
+    pub fn generate_to(&self, node: &LightningD, n: u32) -> anyhow::Result<Vec<BlockHash>> {
+        let script_pubkey = new_script_pubkey(node)?;
+        let hashes = self.state.lock().unwrap().mine(n, &script_pubkey);
+        self.wait_blockheight()?;
+        Ok(hashes)
+    }
+
+    /// Wait until every started node reports the tip height, lower after a reorg to a shorter
+    /// chain
+    fn wait_blockheight(&self) -> anyhow::Result<()> {
+        let (height, rpc_paths) = {
+            let state = self.state.lock().unwrap();
+            let rpc_paths: Vec<_> = state
+                .nodes
+                .values()
+                .filter_map(|node| node.rpc_path.clone())
+                .collect();
+            (state.height(), rpc_paths)
+        };
+        let height = height as u64;
+        for rpc_path in rpc_paths {
+            let client = LightningRPC::new(&rpc_path);
+            let mut current = 0;
+            poll_until(SYNC_TIMEOUT, || {
+                current = client.getinfo()?.blockheight;
+                Ok(Some(()).filter(|_| current == height))
+            })?
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "block height {} not reached after {:?}, at {}",
+                    height,
+                    SYNC_TIMEOUT,
+                    current
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Set the fee rate returned to lightningd for every target, in satoshi per kilo virtual byte
+    pub fn set_feerate(&self, sat_per_kvb: u64) {
+        self.state.lock().unwrap().feerate = sat_per_kvb;
+    }
+
+    /// Reject the following broadcasts with the `reason` error message, or accept them if `None`.
+    ///
+    /// Rejected transactions are still returned by [FakeChain::broadcasts].
+    pub fn reject_broadcasts(&self, reason: Option<&str>) {
+        self.state.lock().unwrap().reject = reason.map(ToString::to_string);
+    }
+
+    /// All the transactions broadcast by lightningd, in order, including the rejected ones
+    pub fn broadcasts(&self) -> Vec<Transaction> {
+        self.state.lock().unwrap().broadcasts.clone()
+    }
+
+    /// Add to the mempool a transaction paying `outputs`, like a faucet, returning its txid.
+    ///
+    /// The transaction spends a made up input, which lightningd doesn't check.
+    pub fn send(&self, outputs: Vec<TxOut>) -> Txid {
+        let mut state = self.state.lock().unwrap();
+        state.sent += 1;
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::new(Txid::hash(&state.sent.to_le_bytes()), 0),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: outputs,
+        };
+        let txid = tx.compute_txid();
+        state.mempool.push(tx);
+        txid
+    }
+
+    /// The confirmed transaction with `txid`, if any
+    pub fn find_tx(&self, txid: &Txid) -> Option<Transaction> {
+        let state = self.state.lock().unwrap();
+        let found = state
+            .blocks
+            .iter()
+            .flat_map(|b| b.txdata.iter())
+            .find(|tx| tx.compute_txid() == *txid)
+            .cloned();
+        found
+    }
+
+    /// The confirmed transaction spending `outpoint`, if any
+    pub fn spent_by(&self, outpoint: &OutPoint) -> Option<Txid> {
+        let state = self.state.lock().unwrap();
+        let found = state
+            .blocks
+            .iter()
+            .flat_map(|b| b.txdata.iter())
+            .find(|tx| tx.input.iter().any(|i| i.previous_output == *outpoint))
+            .map(|tx| tx.compute_txid());
+        found
+    }
+
+    /// Accepted transactions waiting to be confirmed by [FakeChain::mine]
+    pub fn mempool(&self) -> Vec<Transaction> {
+        self.state.lock().unwrap().mempool.clone()
+    }
+
+    /// Replace the top `depth` blocks with `replacement_blocks` new ones, returning their hashes.
+    ///
+    /// The transactions of the disconnected blocks are confirmed again in the first replacement
+    /// block, except the ones in `drop`, which are discarded. With no replacement blocks they are
+    /// left in the mempool. With fewer replacement blocks than `depth` the chain gets shorter and
+    /// the nodes roll back to the lower height.
+    ///
+    /// Returns once every node served by this chain has fetched the new tip and reports its
+    /// height in `getinfo`, or fails after waiting 90 seconds for them.
+    ///
+    /// Nodes poll the chain every second when the dev options are available, see
+    /// [crate::Conf::fake_chain], otherwise every 30 seconds.
+    pub fn reorg(
+        &self,
+        depth: u32,
+        replacement_blocks: u32,
+        drop: &[Txid],
+    ) -> anyhow::Result<Vec<BlockHash>> {
+        let hashes = {
+            let mut state = self.state.lock().unwrap();
+            anyhow::ensure!(
+                depth <= state.height(),
+                "cannot reorg {} blocks, the tip is at height {}",
+                depth,
+                state.height()
+            );
+            let fork = state.blocks.len() - depth as usize;
+            let disconnected = state
+                .blocks
+                .split_off(fork)
+                .into_iter()
+                .flat_map(|block| block.txdata.into_iter().skip(1));
+            let mempool = std::mem::take(&mut state.mempool);
+            state.mempool = disconnected
+                .into_iter()
+                .chain(mempool)
+                .filter(|tx| !drop.contains(&tx.compute_txid()))
+                .collect();
+            for node in state.nodes.values_mut() {
+                node.at_tip = false;
+            }
+            state.mine(replacement_blocks, &anyone_can_spend())
+        };
+        self.wait_nodes(SYNC_TIMEOUT)?;
+        self.wait_blockheight()?;
+        Ok(hashes)
+    }
+
+    /// Wait until every node served by this chain has processed the tip
+    fn wait_nodes(&self, timeout: Duration) -> anyhow::Result<()> {
+        let mut lagging = 0;
+        poll_until(timeout, || {
+            lagging = self
+                .state
+                .lock()
+                .unwrap()
+                .nodes
+                .values()
+                .filter(|node| !node.at_tip)
+                .count();
+            Ok(Some(()).filter(|_| lagging == 0))
+        })?
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "{} nodes not synced to height {} after {:?}",
+                lagging,
+                self.height(),
+                timeout
+            )
+        })
+    }
+
+    /// Start the bitcoin backend plugin serving this chain to a new node
+    pub(crate) fn backend(&self) -> anyhow::Result<Backend> {
+        let node = NODE_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let state = self.state.clone();
+        let plugin = Plugin::start(
+            PLUGIN_NAME,
+            manifest(),
+            Arc::new(move |method, params| state.lock().unwrap().handle(node, method, params)),
+        )?;
+        self.state
+            .lock()
+            .unwrap()
+            .nodes
+            .insert(node, NodeSync::default());
+        Ok(Backend {
+            plugin,
+            node,
+            state: self.state.clone(),
+        })
+    }
+}
+
+impl State {
+    fn height(&self) -> u32 {
+        self.blocks.len() as u32 - 1
+    }
+
+    /// Mine `n` blocks paying to `script_pubkey`, the first one confirming the mempool
+    fn mine(&mut self, n: u32, script_pubkey: &Script) -> Vec<BlockHash> {
+        (0..n)
+            .map(|_| {
+                let txs = std::mem::take(&mut self.mempool);
+                self.mine_block(txs, script_pubkey)
+            })
+            .collect()
+    }
+
+    fn mine_block(&mut self, txs: Vec<Transaction>, script_pubkey: &Script) -> BlockHash {
+        let height = self.height() + 1;
+        let prev = self.blocks.last().expect("genesis").header;
+        let coinbase = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                // BIP34 height, followed by a counter making the script long enough for low heights
+                // and the coinbase of a replacement block different from the reorged one
+                script_sig: Builder::new()
+                    .push_int(height as i64)
+                    .push_int(self.mined as i64)
+                    .into_script(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: SUBSIDY,
+                script_pubkey: script_pubkey.to_owned(),
+            }],
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as u32)
+            .unwrap_or_default();
+        let mut block = Block {
+            header: block::Header {
+                version: block::Version::TWO,
+                prev_blockhash: prev.block_hash(),
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: now.max(prev.time + 1),
+                bits: prev.bits,
+                nonce: 0,
+            },
+            txdata: std::iter::once(coinbase).chain(txs).collect(),
+        };
+        block.header.merkle_root = block.compute_merkle_root().expect("coinbase");
+        // regtest target is met in a couple of attempts
+        while block.header.validate_pow(block.header.target()).is_err() {
+            block.header.nonce += 1;
+        }
+        let hash = block.block_hash();
+        self.blocks.push(block);
+        self.mined += 1;
+        hash
+    }
+
+    /// The unspent output at `outpoint`, considering only confirmed transactions
+    fn utxo(&self, outpoint: &OutPoint) -> Option<TxOut> {
+        let mut outputs: HashMap<OutPoint, TxOut> = HashMap::new();
+        for tx in self.blocks.iter().flat_map(|b| b.txdata.iter()) {
+            for input in tx.input.iter() {
+                outputs.remove(&input.previous_output);
+            }
+            let txid = tx.compute_txid();
+            for (vout, output) in tx.output.iter().enumerate() {
+                outputs.insert(OutPoint::new(txid, vout as u32), output.clone());
+            }
+        }
+        outputs.remove(outpoint)
+    }
+
+    fn handle(&mut self, node: u64, method: &str, params: &Value) -> Result<Value, RpcError> {
+        match method {
+            "getchaininfo" => Ok(json!({
+                "chain": "regtest",
+                "headercount": self.height(),
+                "blockcount": self.height(),
+                "ibd": false,
+            })),
+            "estimatefees" => Ok(self.estimatefees()),
+            "getrawblockbyheight" => {
+                let height = param(params, 0, "height").as_u64().unwrap_or(u64::MAX);
+                let blocks = &self.blocks;
+                let tip = blocks.last().expect("genesis");
+                let mut block = blocks.get(height as usize);
+                if let Some(sync) = self.nodes.get_mut(&node) {
+                    let disconnected = sync.last_block.is_some_and(|(height, hash)| {
+                        blocks.get(height as usize).map(Block::block_hash) != Some(hash)
+                    });
+                    match block {
+                        Some(found) => sync.last_block = Some((height as u32, found.block_hash())),
+                        // the node follows a disconnected branch longer than the chain, which
+                        // lightningd drops only when served a block not extending it, so serve
+                        // the tip until it rolls back below the new tip
+                        None if disconnected => block = Some(tip),
+                        None => {
+                            sync.at_tip =
+                                sync.last_block.map(|(_, hash)| hash) == Some(tip.block_hash())
+                        }
+                    }
+                }
+                Ok(match block {
+                    Some(block) => json!({
+                        "blockhash": block.block_hash().to_string(),
+                        "block": serialize_hex(block),
+                    }),
+                    None => json!({"blockhash": null, "block": null}),
+                })
+            }
+            "getutxout" => {
+                let txid = param(params, 0, "txid").as_str().unwrap_or_default();
+                let txid: Txid = txid
+                    .parse()
+                    .map_err(|_| RpcError::new(-1, &format!("invalid txid {}", txid)))?;
+                let vout = param(params, 1, "vout").as_u64().unwrap_or_default() as u32;
+                Ok(match self.utxo(&OutPoint::new(txid, vout)) {
+                    Some(output) => json!({
+                        "amount": output.value.to_sat(),
+                        "script": output.script_pubkey.to_hex_string(),
+                    }),
+                    None => json!({"amount": null, "script": null}),
+                })
+            }
+            "sendrawtransaction" => {
+                let hex = param(params, 0, "tx").as_str().unwrap_or_default();
+                let tx: Transaction = match deserialize_hex(hex) {
+                    Ok(tx) => tx,
+                    Err(e) => {
+                        return Ok(
+                            json!({"success": false, "errmsg": format!("TX decode failed: {}", e)}),
+                        )
+                    }
+                };
+                self.broadcasts.push(tx.clone());
+                if let Some(reason) = self.reject.as_ref() {
+                    return Ok(json!({"success": false, "errmsg": reason}));
+                }
+                let txid = tx.compute_txid();
+                let known = self
+                    .mempool
+                    .iter()
+                    .chain(self.blocks.iter().flat_map(|b| b.txdata.iter()))
+                    .any(|t| t.compute_txid() == txid);
+                if !known {
+                    self.mempool.push(tx);
+                }
+                Ok(json!({"success": true, "errmsg": ""}))
+            }
+            _ => Err(RpcError::new(
+                -32601,
+                &format!("Unknown command '{}'", method),
+            )),
+        }
+    }
+
+    /// Fee rates in both the format of lightningd v23.05 and newer, and the one of older versions
+    fn estimatefees(&self) -> Value {
+        let feerate = self.feerate.max(FEERATE_FLOOR);
+        let feerates: Vec<_> = [2, 6, 12, 100]
+            .iter()
+            .map(|blocks| json!({"blocks": blocks, "feerate": feerate}))
+            .collect();
+        json!({
+            "feerate_floor": FEERATE_FLOOR,
+            "feerates": feerates,
+            "opening": feerate,
+            "mutual_close": feerate,
+            "unilateral_close": feerate,
+            "delayed_to_us": feerate,
+            "htlc_resolution": feerate,
+            "penalty": feerate,
+            "min_acceptable": FEERATE_FLOOR,
+            "max_acceptable": feerate * 10,
+        })
+    }
+}
+
+/// The script of a new address of the `node` wallet
+pub(crate) fn new_script_pubkey(node: &LightningD) -> anyhow::Result<ScriptBuf> {
+    let result = node.call("newaddr", serde_json::json!([]))?;
+    let address = result["bech32"]
+        .as_str()
+        .ok_or_else(|| anyhow::anyhow!("missing bech32 in {}", result))?;
+    Ok(Address::from_str(address)?
+        .require_network(Network::Regtest)?
+        .script_pubkey())
+}
+
+/// Args making a node poll its backend every second instead of every 30, if the dev options are
+/// available: from v23.11 with `--developer`, before only in developer builds
+pub(crate) fn dev_poll_args(
+    exe: &OsStr,
+    version: Option<Version>,
+    conf_args: &[&str],
+) -> Vec<String> {
+    let poll = "--dev-bitcoind-poll=1".to_string();
+    if version.is_some_and(|v| v >= DEVELOPER_VERSION) {
+        let mut args = vec!["--developer".to_string(), poll];
+        // developer mode disables the deprecated apis, unless explicitly allowed
+        if !conf_args
+            .iter()
+            .any(|arg| arg.contains("allow-deprecated-apis"))
+        {
+            args.push("--allow-deprecated-apis=true".to_string());
+        }
+        return args;
+    }
+    // developer builds list the dev options in the help
+    match Command::new(exe).arg("--help").output() {
+        Ok(output) if String::from_utf8_lossy(&output.stdout).contains("--dev-bitcoind-poll") => {
+            vec![poll]
+        }
+        _ => vec![],
+    }
+}
+
+/// The `OP_TRUE` script, paid by the coinbase of blocks mined with [FakeChain::mine]
+fn anyone_can_spend() -> ScriptBuf {
+    Builder::new().push_opcode(OP_PUSHNUM_1).into_script()
+}
+
+fn manifest() -> Value {
+    let method = |name: &str, usage: &str, description: &str| json!({"name": name, "usage": usage, "description": description});
+    json!({
+        "options": [],
+        "rpcmethods": [
+            method("getchaininfo", "[last_height]", "Chain and tip of the fake chain"),
+            method("estimatefees", "", "Fee rates set by the test"),
+            method("getrawblockbyheight", "height", "Block of the fake chain at height"),
+            method("getutxout", "txid vout", "Confirmed unspent output of the fake chain"),
+            method("sendrawtransaction", "tx [allowhighfees]", "Broadcast to the fake chain"),
+        ],
+        "subscriptions": [],
+        "hooks": [],
+        "dynamic": false,
+    })
+}
+
+/// Fund `funder` and open a channel of `amount_sat` to `peer`, waiting until both nodes can use it
+#[cfg(test)]
+pub(crate) fn open_channel(
+    funder: &LightningD,
+    peer: &LightningD,
+    amount_sat: u64,
+) -> anyhow::Result<()> {
+    let chain = funder
+        .fake_chain()
+        .ok_or_else(|| anyhow::anyhow!("open_channel requires Conf::fake_chain"))?;
+    funder.fund_wallet(2 * amount_sat)?;
+    funder.connect(peer)?;
+    funder.call("fundchannel", json!([peer.node_id()?, amount_sat]))?;
+    chain.generate(6)?;
+    for node in [funder, peer].iter() {
+        poll_until(SYNC_TIMEOUT, || {
+            let channels = node.channels()?;
+            Ok(Some(()).filter(|_| channels[0]["state"] == "CHANNELD_NORMAL"))
+        })?
+        .ok_or_else(|| anyhow::anyhow!("channel not active"))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{dev_poll_args, FakeChain, NodeSync};
+    use crate::{exe_path, poll_until, Conf, LightningD, Version, SYNC_TIMEOUT};
+    use bitcoin::consensus::encode::{deserialize_hex, serialize_hex};
+    use bitcoin::{Block, OutPoint, Transaction};
+    use serde_json::json;
+    use std::time::Duration;
+
+    #[test]
+    fn test_fake_chain() {
+        let chain = FakeChain::new();
+        let hashes = chain.mine(3);
+        assert_eq!(chain.height(), 3);
+        let mut state = chain.state.lock().unwrap();
+
+        let info = state
+            .handle(0, "getchaininfo", &json!({"last_height": 0}))
+            .unwrap();
+        assert_eq!(info["blockcount"], 3);
+        let raw = state
+            .handle(0, "getrawblockbyheight", &json!({"height": 2}))
+            .unwrap();
+        assert_eq!(raw["blockhash"], hashes[1].to_string());
+        let block: Block = deserialize_hex(raw["block"].as_str().unwrap()).unwrap();
+        assert_eq!(block.header.prev_blockhash, state.blocks[1].block_hash());
+        assert_ne!(
+            block.txdata[0].compute_txid(),
+            state.blocks[1].txdata[0].compute_txid()
+        );
+        let raw = state
+            .handle(0, "getrawblockbyheight", &json!({"height": 4}))
+            .unwrap();
+        assert!(raw["block"].is_null());
+
+        // spend the coinbase of block 1
+        let coinbase = state.blocks[1].txdata[0].clone();
+        let mut tx: Transaction = coinbase.clone();
+        tx.input[0].previous_output = OutPoint::new(coinbase.compute_txid(), 0);
+        let params = json!({"txid": coinbase.compute_txid().to_string(), "vout": 0});
+        let utxo = state.handle(0, "getutxout", &params).unwrap();
+        assert_eq!(utxo["amount"], 50 * 100_000_000u64);
+        let sent = state
+            .handle(0, "sendrawtransaction", &json!([serialize_hex(&tx), false]))
+            .unwrap();
+        assert_eq!(sent["success"], true);
+        drop(state);
+
+        assert_eq!(chain.mempool(), vec![tx.clone()]);
+        chain.mine(1);
+        assert!(chain.mempool().is_empty());
+        assert_eq!(chain.block(4).unwrap().txdata[1], tx);
+        let mut state = chain.state.lock().unwrap();
+        let utxo = state.handle(0, "getutxout", &params).unwrap();
+        assert!(utxo["amount"].is_null());
+        drop(state);
+
+        chain.reject_broadcasts(Some("min relay fee not met"));
+        chain.set_feerate(5_000);
+        let mut state = chain.state.lock().unwrap();
+        let sent = state
+            .handle(0, "sendrawtransaction", &json!({"tx": serialize_hex(&tx)}))
+            .unwrap();
+        assert_eq!(sent["errmsg"], "min relay fee not met");
+        let fees = state.handle(0, "estimatefees", &json!({})).unwrap();
+        assert_eq!(fees["feerates"][0]["feerate"], 5_000);
+        drop(state);
+        assert_eq!(chain.broadcasts().len(), 2);
+        assert!(chain.mempool().is_empty());
+    }
+
+    #[test]
+    fn test_getchaininfo() {
+        let chain = FakeChain::new();
+        chain.mine(2);
+        let info = chain
+            .state
+            .lock()
+            .unwrap()
+            .handle(0, "getchaininfo", &json!({"last_height": 0}))
+            .unwrap();
+        // the fields lightningd requires, failing the plugin if any is missing
+        assert_eq!(info["chain"], "regtest");
+        assert_eq!(info["headercount"], 2);
+        assert_eq!(info["blockcount"], 2);
+        assert_eq!(info["ibd"], false);
+        assert_eq!(info.as_object().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_reorg() {
+        let chain = FakeChain::new();
+        chain.mine(5);
+        let coinbase = chain.block(1).unwrap().txdata[0].clone();
+        let mut tx = coinbase.clone();
+        tx.input[0].previous_output = OutPoint::new(coinbase.compute_txid(), 0);
+        chain.state.lock().unwrap().mempool.push(tx.clone());
+        let old = chain.mine(1)[0];
+        assert_eq!(chain.height(), 6);
+
+        // the transaction is confirmed again in the first replacement block
+        let hashes = chain.reorg(2, 3, &[]).unwrap();
+        assert_eq!(chain.height(), 7);
+        assert_eq!(hashes[0], chain.block(5).unwrap().block_hash());
+        assert_ne!(hashes[1], old);
+        assert_eq!(chain.block(5).unwrap().txdata[1], tx);
+
+        let hashes = chain.reorg(3, 1, &[tx.compute_txid()]).unwrap();
+        assert_eq!(chain.height(), 5);
+        assert_eq!(chain.block(5).unwrap().block_hash(), hashes[0]);
+        assert_eq!(chain.block(5).unwrap().txdata.len(), 1);
+        assert!(chain.mempool().is_empty());
+
+        chain.reorg(1, 0, &[]).unwrap();
+        assert_eq!(chain.height(), 4);
+        assert!(chain.reorg(5, 0, &[]).is_err());
+
+        // a node is synced once it asks for the block after the new tip
+        chain
+            .state
+            .lock()
+            .unwrap()
+            .nodes
+            .insert(0, NodeSync::default());
+        assert!(chain.wait_nodes(Duration::ZERO).is_err());
+        let mut state = chain.state.lock().unwrap();
+        for height in 3..=5 {
+            state
+                .handle(0, "getrawblockbyheight", &json!({ "height": height }))
+                .unwrap();
+        }
+        drop(state);
+        chain.wait_nodes(Duration::ZERO).unwrap();
+
+        // a node on a branch longer than the new chain is served the tip until it rolls back
+        let reorg = {
+            let chain = chain.clone();
+            std::thread::spawn(move || chain.reorg(2, 1, &[]))
+        };
+        poll_until(
+            SYNC_TIMEOUT,
+            || Ok(Some(()).filter(|_| chain.height() == 3)),
+        )
+        .unwrap()
+        .unwrap();
+        let tip = chain.block(3).unwrap().block_hash().to_string();
+        let mut state = chain.state.lock().unwrap();
+        for height in [5, 4, 3] {
+            let raw = state
+                .handle(0, "getrawblockbyheight", &json!({ "height": height }))
+                .unwrap();
+            assert_eq!(raw["blockhash"], tip);
+        }
+        let raw = state
+            .handle(0, "getrawblockbyheight", &json!({ "height": 4 }))
+            .unwrap();
+        assert!(raw["blockhash"].is_null());
+        drop(state);
+        assert_eq!(reorg.join().unwrap().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_dev_poll_args() {
+        let exe = std::ffi::OsStr::new("/nonexistent/lightningd");
+        let args = dev_poll_args(exe, Some(Version::new(24, 11, 0)), &[]);
+        assert_eq!(
+            args,
+            vec![
+                "--developer",
+                "--dev-bitcoind-poll=1",
+                "--allow-deprecated-apis=true"
+            ]
+        );
+        let args = dev_poll_args(
+            exe,
+            Some(Version::new(23, 11, 0)),
+            &["--allow-deprecated-apis=false"],
+        );
+        assert_eq!(args, vec!["--developer", "--dev-bitcoind-poll=1"]);
+        // older versions need a developer build
+        assert!(dev_poll_args(exe, Some(Version::new(23, 5, 2)), &[]).is_empty());
+        assert!(dev_poll_args(exe, None, &[]).is_empty());
+    }
+
+    #[test]
+    fn test_lightningd_fake_chain() {
+        let _ = env_logger::try_init();
+        let chain = FakeChain::new();
+        chain.mine(101);
+        let conf = Conf {
+            fake_chain: Some(chain.clone()),
+            ..Default::default()
+        };
+        let lightningd = LightningD::with_conf(exe_path().unwrap(), &conf).unwrap();
+        lightningd.sync();
+        assert_eq!(lightningd.client.getinfo().unwrap().blockheight, 101);
+    }
+
+    #[test]
+    fn test_lightningd_reorg() {
+        let _ = env_logger::try_init();
+        let chain = FakeChain::new();
+        chain.mine(101);
+        let conf = Conf {
+            fake_chain: Some(chain.clone()),
+            ..Default::default()
+        };
+        let lightningd = LightningD::with_conf(exe_path().unwrap(), &conf).unwrap();
+        let hashes = chain.reorg(3, 4, &[]).unwrap();
+        assert_eq!(hashes.len(), 4);
+        assert_eq!(lightningd.client.getinfo().unwrap().blockheight, 102);
+    }
+
+    #[test]
+    fn test_lightningd_reorg_shorter() {
+        let _ = env_logger::try_init();
+        let chain = FakeChain::new();
+        chain.mine(101);
+        let conf = Conf {
+            fake_chain: Some(chain.clone()),
+            ..Default::default()
+        };
+        let lightningd = LightningD::with_conf(exe_path().unwrap(), &conf).unwrap();
+        chain.reorg(3, 1, &[]).unwrap();
+        assert_eq!(lightningd.client.getinfo().unwrap().blockheight, 99);
+        chain.generate(2).unwrap();
+        assert_eq!(lightningd.client.getinfo().unwrap().blockheight, 101);
+    }
+
+    #[test]
+    fn test_generate_to() {
+        let _ = env_logger::try_init();
+        let chain = FakeChain::new();
+        let conf = Conf {
+            fake_chain: Some(chain.clone()),
+            ..Default::default()
+        };
+        let alice = LightningD::with_conf(exe_path().unwrap(), &conf).unwrap();
+        let bob = LightningD::with_conf(exe_path().unwrap(), &conf).unwrap();
+        chain.generate_to(&alice, 1).unwrap();
+        chain.generate(100).unwrap();
+        assert_eq!(bob.client.getinfo().unwrap().blockheight, 101);
+        alice.wait_for_blockheight(101).unwrap();
+        let funds = alice.client.listfunds().unwrap();
+        assert_eq!(funds.outputs.len(), 1);
+    }
+}
@@ -0,0 +1,250 @@
+use crate::LightningD;
+use bitcoin::{OutPoint, Txid};
+use serde_json::{json, Value};
+use std::str::FromStr;
+
+/// Maximum number of blocks mined waiting for the resolution, more than the maximum to-self delay
+/// accepted by lightningd
+const MAX_BLOCKS: u32 = 2100;
+
+/// An output of the commitment transaction and the transaction resolving it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolvedOutput {
+    /// Output of the commitment transaction
+    pub outpoint: OutPoint,
+    /// Value of the output
+    pub amount_sat: u64,
+    /// Confirmed transaction spending the output
+    pub spent_by: Txid,
+}
+
+/// Outcome of [LightningD::force_close]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForceClose {
+    /// The commitment transaction broadcast to close the channel
+    pub closing_txid: Txid,
+    /// Blocks mined until both sides resolved their outputs
+    pub blocks_mined: u32,
+    /// Outputs of the commitment transaction spent on chain, the ones not listed are unspent,
+    /// like the ones paying to the peer wallet directly
+    pub resolved: Vec<ResolvedOutput>,
+    /// Confirmed wallet balance of the closing node at the end
+    pub balance_sat: u64,
+    /// Confirmed wallet balance of the peer at the end
+    pub peer_balance_sat: u64,
+}
+
+/// Requires nodes started with [crate::Conf::fake_chain]
+impl LightningD {
+    /// Unilaterally close the channel with `peer`, then mine through the to-self delays and the
+    /// HTLC timeouts until `onchaind` of both nodes resolved all their outputs.
+    ///
+    /// The peer is disconnected first, failing if it reconnects before the close times out.
+    pub fn force_close(&self, peer: &LightningD) -> anyhow::Result<ForceClose> {
+        let chain = self
+            .fake_chain()
+            .ok_or_else(|| anyhow::anyhow!("force_close requires Conf::fake_chain"))?;
+        let peer_id = peer.node_id()?;
+        let channel_id = self
+            .channels()?
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|c| c["peer_id"] == peer_id.as_str() && c["state"] == "CHANNELD_NORMAL")
+            .and_then(|c| c["channel_id"].as_str().map(ToString::to_string))
+            .ok_or_else(|| anyhow::anyhow!("no active channel with {}", peer_id))?;
+
+        // with the peer connected `close` would negotiate a mutual close, so disconnect first and
+        // let it time out after a second
+        self.call("disconnect", json!([peer_id, true]))?;
+        let result = self.call("close", json!([channel_id, 1]))?;
+        anyhow::ensure!(
+            result["type"] == "unilateral",
+            "channel {} closed as {}, the peer reconnected",
+            channel_id,
+            result["type"]
+        );
+        let closing_txid = result["txid"]
+            .as_str()
+            .ok_or_else(|| anyhow::anyhow!("missing txid in {}", result))?;
+        let closing_txid = Txid::from_str(closing_txid)?;
+
+        let mut blocks_mined = 0;
+        loop {
+            let mut hints = vec![];
+            let mut resolved = true;
+            for node in [self, peer].iter() {
+                match onchain_status(node, &channel_id)? {
+                    Some(status) if !status.iter().any(|s| s.contains("All outputs resolved")) => {
+                        resolved = false;
+                        hints.extend(status.iter().filter_map(|s| blocks_hint(s)));
+                    }
+                    _ => {}
+                }
+            }
+            if resolved && chain.mempool().is_empty() {
+                break;
+            }
+            anyhow::ensure!(
+                blocks_mined < MAX_BLOCKS,
+                "channel {} not resolved after {} blocks",
+                channel_id,
+                blocks_mined
+            );
+            // broadcasts need a block to confirm, then mine through the shortest delay
+            let n = if chain.mempool().is_empty() {
+                hints.into_iter().min().unwrap_or(1).max(1)
+            } else {
+                1
+            };
+            chain.generate(n)?;
+            blocks_mined += n;
+        }
+
+        let closing_tx = chain
+            .find_tx(&closing_txid)
+            .ok_or_else(|| anyhow::anyhow!("{} not confirmed", closing_txid))?;
+        let resolved = (0..closing_tx.output.len() as u32)
+            .filter_map(|vout| {
+                let outpoint = OutPoint::new(closing_txid, vout);
+                chain.spent_by(&outpoint).map(|spent_by| ResolvedOutput {
+                    outpoint,
+                    amount_sat: closing_tx.output[vout as usize].value.to_sat(),
+                    spent_by,
+                })
+            })
+            .collect();
+
+        Ok(ForceClose {
+            closing_txid,
+            blocks_mined,
+            resolved,
+            balance_sat: confirmed_balance_sat(self)?,
+            peer_balance_sat: confirmed_balance_sat(peer)?,
+        })
+    }
+}
+
+/// The `status` of the channel in `ONCHAIN` state, empty in the states before, or `None` if the
+/// channel is forgotten
+fn onchain_status(node: &LightningD, channel_id: &str) -> anyhow::Result<Option<Vec<String>>> {
+    let channels = node.channels()?;
+    let channel = channels
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|c| c["channel_id"] == channel_id);
+    let channel = match channel {
+        Some(channel) => channel,
+        None => return Ok(None),
+    };
+    let status = channel["status"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|s| s.as_str().map(ToString::to_string))
+        .collect();
+    Ok(match channel["state"].as_str() {
+        Some("ONCHAIN") => Some(status),
+        // the closing transaction is not yet processed by onchaind
+        _ => Some(vec![]),
+    })
+}
+
+/// Blocks to wait for an output resolution, from onchaind status like
+/// `OUR_UNILATERAL/DELAYED_OUTPUT_TO_US->OUR_DELAYED_RETURN_TO_WALLET in 143 blocks`
+fn blocks_hint(status: &str) -> Option<u32> {
+    if status.contains("forgetting") {
+        return None;
+    }
+    let words: Vec<_> = status.split_whitespace().collect();
+    words
+        .windows(2)
+        .filter(|w| w[1].starts_with("block"))
+        .filter_map(|w| w[0].parse().ok())
+        .min()
+}
+
+/// Sum of the confirmed outputs of the node wallet
+fn confirmed_balance_sat(node: &LightningD) -> anyhow::Result<u64> {
+    let result = node.call("listfunds", json!([]))?;
+    let mut balance = 0;
+    for output in result["outputs"].as_array().into_iter().flatten() {
+        if output["status"] != "confirmed" {
+            continue;
+        }
+        balance += match (&output["amount_msat"], &output["value"]) {
+            (Value::Number(msat), _) => msat.as_u64().unwrap_or_default() / 1000,
+            (Value::String(msat), _) => {
+                msat.trim_end_matches("msat")
+                    .parse::<u64>()
+                    .unwrap_or_default()
+                    / 1000
+            }
+            (_, value) => value.as_u64().unwrap_or_default(),
+        };
+    }
+    Ok(balance)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{blocks_hint, confirmed_balance_sat};
+    use crate::chain::open_channel;
+    use crate::{exe_path, Conf, FakeChain, LightningD};
+    use serde_json::json;
+
+    #[test]
+    fn test_blocks_hint() {
+        assert_eq!(
+            blocks_hint("ONCHAIN:Tracking our own unilateral close"),
+            None
+        );
+        assert_eq!(
+            blocks_hint("ONCHAIN:1 outputs unresolved: in 143 blocks will spend DELAYED_OUTPUT_TO_US (1c2d:0) using OUR_DELAYED_RETURN_TO_WALLET"),
+            Some(143)
+        );
+        assert_eq!(
+            blocks_hint(
+                "ONCHAIN:All outputs resolved: waiting 90 more blocks before forgetting channel"
+            ),
+            None
+        );
+        assert_eq!(blocks_hint("after 6 blocks or in 1 block"), Some(1));
+    }
+
+    #[test]
+    fn test_force_close() {
+        let _ = env_logger::try_init();
+        let conf = Conf {
+            fake_chain: Some(FakeChain::new()),
+            ..Default::default()
+        };
+        let alice = LightningD::with_conf(exe_path().unwrap(), &conf).unwrap();
+        let bob = LightningD::with_conf(exe_path().unwrap(), &conf).unwrap();
+        open_channel(&alice, &bob, 500_000).unwrap();
+        // give bob a balance in the channel, paid to his wallet on close
+        let invoice = bob.create_invoice(100_000_000, "close").unwrap();
+        alice.pay_invoice(&invoice.bolt11).unwrap();
+        let to_self_delay = alice.channels().unwrap()[0]["our_to_self_delay"]
+            .as_u64()
+            .unwrap();
+        let alice_before = confirmed_balance_sat(&alice).unwrap();
+        let bob_before = confirmed_balance_sat(&bob).unwrap();
+
+        let closed = alice.force_close(&bob).unwrap();
+        assert!(closed.blocks_mined as u64 >= to_self_delay);
+        assert!(closed.balance_sat > alice_before);
+        assert!(closed.peer_balance_sat > bob_before);
+
+        // the to-self output of alice is swept to her wallet
+        let funds = alice.call("listfunds", json!([])).unwrap();
+        let swept =
+            closed.resolved.iter().any(|resolved| {
+                funds["outputs"].as_array().unwrap().iter().any(|o| {
+                    o["txid"] == resolved.spent_by.to_string() && o["status"] == "confirmed"
+                })
+            });
+        assert!(swept, "no resolved output in {}", funds);
+    }
+}
@@ -0,0 +1,50 @@
+use crate::LightningD;
+use serde_json::{json, Value};
+
+impl LightningD {
+    /// Call `method` with `params` on `peer` through `commando`, over the lightning connection
+    /// between the nodes, authorized by `rune` created on `peer`. Returns the result of `method`.
+    ///
+    /// The nodes must be connected, see [LightningD::connect].
+    pub fn commando(
+        &self,
+        peer: &LightningD,
+        rune: &str,
+        method: &str,
+        params: Value,
+    ) -> anyhow::Result<Value> {
+        let request = json!({
+            "peer_id": peer.node_id()?,
+            "method": method,
+            "params": params,
+            "rune": rune,
+        });
+        self.call("commando", request)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{exe_path, LightningD, RuneRestrictions};
+    use serde_json::json;
+
+    #[test]
+    fn test_commando() {
+        let _ = env_logger::try_init();
+        let alice = LightningD::new(exe_path().unwrap()).unwrap();
+        let bob = LightningD::new(exe_path().unwrap()).unwrap();
+        alice.connect(&bob).unwrap();
+        let restrictions = RuneRestrictions::new()
+            .allow_methods(&["getinfo"])
+            .id(&alice.node_id().unwrap());
+        let rune = bob.create_rune(&restrictions).unwrap();
+
+        let info = alice
+            .commando(&bob, &rune.rune, "getinfo", json!({}))
+            .unwrap();
+        assert_eq!(info["id"], bob.node_id().unwrap());
+        assert!(alice
+            .commando(&bob, &rune.rune, "listfunds", json!({}))
+            .is_err());
+    }
+}
@@ -0,0 +1,301 @@
+use crate::backup::WALLET_FILE;
+use crate::LightningD;
+use rusqlite::types::ValueRef;
+use rusqlite::{Connection, OpenFlags, Row};
+use std::path::{Path, PathBuf};
+
+/// A row of the `invoices` table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DbInvoice {
+    /// Label of the invoice
+    pub label: String,
+    /// 0 unpaid, 1 paid, 2 expired
+    pub state: i64,
+    /// Amount requested, `None` for invoices of any amount
+    pub msat: Option<u64>,
+    /// Amount received, once paid
+    pub received_msat: Option<u64>,
+    /// Hex encoded payment hash
+    pub payment_hash: String,
+    /// Order of the payment among the paid invoices, once paid
+    pub pay_index: Option<u64>,
+}
+
+/// A row of the `forwards` table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DbForward {
+    /// Short channel id of the incoming channel, as integer
+    pub in_channel_scid: Option<u64>,
+    /// Short channel id of the outgoing channel, as integer, missing for local failures
+    pub out_channel_scid: Option<u64>,
+    /// Amount received in the incoming channel
+    pub in_msat: u64,
+    /// Amount sent in the outgoing channel
+    pub out_msat: Option<u64>,
+    /// 0 offered, 1 settled, 2 failed, 3 local failed
+    pub state: i64,
+    /// Time at which the HTLC was received, in nanoseconds since the Unix epoch
+    pub received_time: u64,
+    /// Time at which the forward was resolved, in nanoseconds since the Unix epoch
+    pub resolved_time: Option<u64>,
+}
+
+/// A row of the `channel_htlcs` table
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DbHtlc {
+    /// Database id of the channel, the `id` of the `channels` table
+    pub channel_id: i64,
+    /// Id of the HTLC in the channel
+    pub channel_htlc_id: u64,
+    /// 0 incoming, 1 outgoing
+    pub direction: i64,
+    /// Amount of the HTLC
+    pub msat: u64,
+    /// Block height at which the HTLC expires
+    pub cltv_expiry: u32,
+    /// Hex encoded payment hash
+    pub payment_hash: String,
+    /// State of the HTLC in the commitment dance, the `htlc_state` enum of lightningd
+    pub hstate: i64,
+}
+
+/// A read-only connection to the sqlite wallet of a node, see [LightningD::db]
+#[derive(Debug)]
+pub struct Db {
+    connection: Connection,
+}
+
+impl Db {
+    fn open(path: &Path) -> anyhow::Result<Db> {
+        let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+        let connection = Connection::open_with_flags(path, flags)?;
+        Ok(Db { connection })
+    }
+
+    fn journal_mode(&self) -> anyhow::Result<String> {
+        Ok(self
+            .connection
+            .query_row("PRAGMA journal_mode", [], |row| row.get(0))?)
+    }
+
+    /// The underlying connection, for the queries not covered by the typed ones
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// All the invoices, in creation order
+    pub fn invoices(&self) -> anyhow::Result<Vec<DbInvoice>> {
+        self.query(
+            "SELECT label, state, msatoshi, msatoshi_received, payment_hash, pay_index \
+             FROM invoices ORDER BY id",
+            |row| {
+                Ok(DbInvoice {
+                    label: text(row, 0)?,
+                    state: row.get(1)?,
+                    msat: row.get(2)?,
+                    received_msat: row.get(3)?,
+                    payment_hash: hex(row, 4)?,
+                    pay_index: row.get(5)?,
+                })
+            },
+        )
+    }
+
+    /// All the forwards, in order of arrival.
+    ///
+    /// Requires a node of version 22.11 or newer, the first storing the `forwards` table
+    pub fn forwards(&self) -> anyhow::Result<Vec<DbForward>> {
+        self.query(
+            "SELECT in_channel_scid, out_channel_scid, in_msatoshi, out_msatoshi, state, \
+             received_time, resolved_time FROM forwards ORDER BY received_time",
+            |row| {
+                Ok(DbForward {
+                    in_channel_scid: row.get(0)?,
+                    out_channel_scid: row.get(1)?,
+                    in_msat: row.get(2)?,
+                    out_msat: row.get(3)?,
+                    state: row.get(4)?,
+                    received_time: row.get(5)?,
+                    resolved_time: row.get(6)?,
+                })
+            },
+        )
+    }
+
+    /// All the HTLCs still tracked in the channels
+    pub fn htlcs(&self) -> anyhow::Result<Vec<DbHtlc>> {
+        self.query(
+            "SELECT channel_id, channel_htlc_id, direction, msatoshi, cltv_expiry, payment_hash, \
+             hstate FROM channel_htlcs ORDER BY id",
+            |row| {
+                Ok(DbHtlc {
+                    channel_id: row.get(0)?,
+                    channel_htlc_id: row.get(1)?,
+                    direction: row.get(2)?,
+                    msat: row.get(3)?,
+                    cltv_expiry: row.get(4)?,
+                    payment_hash: hex(row, 5)?,
+                    hstate: row.get(6)?,
+                })
+            },
+        )
+    }
+
+    fn query<T>(
+        &self,
+        sql: &str,
+        f: impl FnMut(&Row<'_>) -> rusqlite::Result<T>,
+    ) -> anyhow::Result<Vec<T>> {
+        let mut statement = self.connection.prepare(sql)?;
+        let rows = statement.query_map([], f)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+/// A column stored as text or, by some versions, as blob
+fn text(row: &Row<'_>, index: usize) -> rusqlite::Result<String> {
+    Ok(match row.get_ref(index)? {
+        ValueRef::Text(bytes) | ValueRef::Blob(bytes) => String::from_utf8_lossy(bytes).to_string(),
+        _ => String::new(),
+    })
+}
+
+/// A blob column as hex
+fn hex(row: &Row<'_>, index: usize) -> rusqlite::Result<String> {
+    Ok(match row.get_ref(index)? {
+        ValueRef::Blob(bytes) => bytes.iter().map(|b| format!("{:02x}", b)).collect(),
+        _ => String::new(),
+    })
+}
+
+impl LightningD {
+    /// Open a read-only connection to the sqlite wallet of the node.
+    ///
+    /// Reading the wallet of a running node could see or block its transactions, so it's
+    /// allowed only when the node is stopped, see [LightningD::stop], or when the wallet is in
+    /// WAL journal mode, where readers don't interfere with the writer.
+    pub fn db(&mut self) -> anyhow::Result<Db> {
+        let path = self.wallet_path()?;
+        let running = self.process.try_wait()?.is_none();
+        let db = Db::open(&path)?;
+        if running {
+            let mode = db.journal_mode()?;
+            anyhow::ensure!(
+                mode.eq_ignore_ascii_case("wal"),
+                "the wallet of a running node is in {} journal mode, stop the node first",
+                mode
+            );
+        }
+        Ok(db)
+    }
+
+    /// Path of the sqlite wallet, the main one if replicated
+    fn wallet_path(&self) -> anyhow::Result<PathBuf> {
+        match self.wallet_dsn() {
+            None => Ok(self.network_dir()?.join(WALLET_FILE)),
+            Some(dsn) => dsn
+                .strip_prefix("sqlite3://")
+                .and_then(|paths| paths.split(':').next())
+                .map(PathBuf::from)
+                .ok_or_else(|| anyhow::anyhow!("the wallet {} is not a sqlite file", dsn)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Db, DbForward, DbHtlc, DbInvoice};
+    use crate::{exe_path, LightningD};
+    use rusqlite::{params, Connection};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_db_queries() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("lightningd.sqlite3");
+        let connection = Connection::open(&path).unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE invoices (id INTEGER PRIMARY KEY, state INTEGER, msatoshi INTEGER, \
+                 msatoshi_received INTEGER, payment_hash BLOB, label TEXT, pay_index INTEGER);
+                 CREATE TABLE forwards (in_channel_scid INTEGER, out_channel_scid INTEGER, \
+                 in_msatoshi INTEGER, out_msatoshi INTEGER, state INTEGER, \
+                 received_time INTEGER, resolved_time INTEGER);
+                 CREATE TABLE channel_htlcs (id INTEGER PRIMARY KEY, channel_id INTEGER, \
+                 channel_htlc_id INTEGER, direction INTEGER, msatoshi INTEGER, \
+                 cltv_expiry INTEGER, payment_hash BLOB, hstate INTEGER);",
+            )
+            .unwrap();
+        connection
+            .execute(
+                "INSERT INTO invoices VALUES (1, 1, 1000, 1000, ?1, 'l', 1)",
+                params![vec![0xabu8; 2]],
+            )
+            .unwrap();
+        connection
+            .execute(
+                "INSERT INTO forwards VALUES (1, NULL, 2000, NULL, 3, 1700000000000000000, \
+                 1700000000500000000)",
+                [],
+            )
+            .unwrap();
+        connection
+            .execute(
+                "INSERT INTO channel_htlcs VALUES (1, 2, 0, 1, 3000, 150, ?1, 4)",
+                params![vec![0x01u8]],
+            )
+            .unwrap();
+
+        let db = Db::open(&path).unwrap();
+        assert_eq!(
+            db.invoices().unwrap(),
+            vec![DbInvoice {
+                label: "l".to_string(),
+                state: 1,
+                msat: Some(1000),
+                received_msat: Some(1000),
+                payment_hash: "abab".to_string(),
+                pay_index: Some(1),
+            }]
+        );
+        assert_eq!(
+            db.forwards().unwrap(),
+            vec![DbForward {
+                in_channel_scid: Some(1),
+                out_channel_scid: None,
+                in_msat: 2000,
+                out_msat: None,
+                state: 3,
+                received_time: 1_700_000_000_000_000_000,
+                resolved_time: Some(1_700_000_000_500_000_000),
+            }]
+        );
+        assert_eq!(
+            db.htlcs().unwrap(),
+            vec![DbHtlc {
+                channel_id: 2,
+                channel_htlc_id: 0,
+                direction: 1,
+                msat: 3000,
+                cltv_expiry: 150,
+                payment_hash: "01".to_string(),
+                hstate: 4,
+            }]
+        );
+        assert!(db.connection().execute("DELETE FROM invoices", []).is_err());
+    }
+
+    #[test]
+    fn test_db() {
+        let _ = env_logger::try_init();
+        let mut lightningd = LightningD::new(exe_path().unwrap()).unwrap();
+        let invoice = lightningd.create_invoice(1000, "db").unwrap();
+        assert!(lightningd.db().is_err());
+
+        lightningd.stop().unwrap();
+        let invoices = lightningd.db().unwrap().invoices().unwrap();
+        assert_eq!(invoices.len(), 1);
+        assert_eq!(invoices[0].label, invoice.label);
+        assert_eq!(invoices[0].payment_hash, invoice.payment_hash);
+    }
+}
@@ -0,0 +1,335 @@
+use crate::payment::msat;
+use crate::plugin::Plugin;
+use crate::LightningD;
+use log::debug;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+use std::time::{Duration, Instant};
+
+/// Name of the plugin forwarding the notifications to the test process
+const PLUGIN_NAME: &str = "events-bridge";
+
+/// Payload of the `invoice_payment` notification
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct InvoicePayment {
+    /// Label of the paid invoice
+    pub label: String,
+    /// Preimage of the payment hash
+    pub preimage: String,
+    /// Amount received
+    #[serde(deserialize_with = "msat")]
+    pub msat: u64,
+}
+
+/// Payload of the `forward_event` notification
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ForwardEvent {
+    /// Hash of the forwarded payment
+    pub payment_hash: Option<String>,
+    /// Short channel id of the incoming channel
+    pub in_channel: String,
+    /// Short channel id of the outgoing channel, missing if the forward failed locally
+    pub out_channel: Option<String>,
+    /// Amount received in the incoming channel
+    #[serde(deserialize_with = "msat")]
+    pub in_msat: u64,
+    /// One of `offered`, `settled`, `failed` and `local_failed`
+    pub status: String,
+    /// Reason of the failure, for `local_failed` forwards
+    pub failreason: Option<String>,
+}
+
+/// Payload of the `channel_state_changed` notification
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ChannelStateChanged {
+    /// Id of the peer
+    pub peer_id: String,
+    /// Id of the channel
+    pub channel_id: String,
+    /// Short channel id, once the funding transaction is confirmed
+    pub short_channel_id: Option<String>,
+    /// State before the change, missing for new channels in recent versions
+    pub old_state: Option<String>,
+    /// State after the change, like `CHANNELD_NORMAL`
+    pub new_state: String,
+    /// What caused the change, like `user` or `remote`
+    pub cause: String,
+    /// Human readable description of the change
+    pub message: String,
+}
+
+/// Payload of the `connect` notification
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Connect {
+    /// Id of the peer
+    pub id: String,
+    /// `in` if the peer connected to us, `out` otherwise
+    pub direction: Option<String>,
+    /// Address of the peer
+    pub address: Value,
+}
+
+/// Payload of the `disconnect` notification
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Disconnect {
+    /// Id of the peer
+    pub id: String,
+}
+
+/// A notification emitted by lightningd for a topic in [crate::Conf::events]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// `invoice_payment`
+    InvoicePayment(InvoicePayment),
+    /// `forward_event`
+    ForwardEvent(ForwardEvent),
+    /// `channel_state_changed`
+    ChannelStateChanged(ChannelStateChanged),
+    /// `connect`
+    Connect(Connect),
+    /// `disconnect`
+    Disconnect(Disconnect),
+    /// Any other topic, or a payload not matching the typed ones
+    Other {
+        /// The notification topic
+        topic: String,
+        /// The notification params
+        params: Value,
+    },
+}
+
+impl Event {
+    fn parse(topic: &str, params: &Value) -> Event {
+        // recent versions wrap the payload in an object keyed by the topic
+        let payload = params.get(topic).unwrap_or(params);
+        let typed = match topic {
+            "invoice_payment" => typed(payload).map(Event::InvoicePayment),
+            "forward_event" => typed(payload).map(Event::ForwardEvent),
+            "channel_state_changed" => typed(payload).map(Event::ChannelStateChanged),
+            "connect" => typed(payload).map(Event::Connect),
+            "disconnect" => typed(payload).map(Event::Disconnect),
+            _ => None,
+        };
+        typed.unwrap_or_else(|| Event::Other {
+            topic: topic.to_string(),
+            params: params.clone(),
+        })
+    }
+
+    /// The notification topic of this event
+    pub fn topic(&self) -> &str {
+        match self {
+            Event::InvoicePayment(_) => "invoice_payment",
+            Event::ForwardEvent(_) => "forward_event",
+            Event::ChannelStateChanged(_) => "channel_state_changed",
+            Event::Connect(_) => "connect",
+            Event::Disconnect(_) => "disconnect",
+            Event::Other { topic, .. } => topic,
+        }
+    }
+}
+
+fn typed<T: DeserializeOwned>(payload: &Value) -> Option<T> {
+    serde_json::from_value(payload.clone())
+        .map_err(|e| debug!("untyped event {}: {:?}", payload, e))
+        .ok()
+}
+
+/// The plugin subscribed to the notifications of a node and the events it received
+#[derive(Debug)]
+pub(crate) struct EventBridge {
+    plugin: Plugin,
+    receiver: Mutex<Receiver<Event>>,
+}
+
+impl EventBridge {
+    /// Start the plugin subscribing to `topics`, `*` subscribes to all of them in v23.08 or newer
+    pub(crate) fn start(topics: &[&str]) -> anyhow::Result<EventBridge> {
+        let manifest = json!({
+            "options": [],
+            "rpcmethods": [],
+            "subscriptions": topics,
+            "dynamic": false,
+        });
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let plugin = Plugin::start(
+            PLUGIN_NAME,
+            manifest,
+            Arc::new(move |topic, params| {
+                // nobody listening is not an error, the events are just discarded
+                let _ = sender.lock().unwrap().send(Event::parse(topic, params));
+                Ok(json!({}))
+            }),
+        )?;
+        Ok(EventBridge {
+            plugin,
+            receiver: Mutex::new(receiver),
+        })
+    }
+
+    pub(crate) fn plugin(&self) -> &Plugin {
+        &self.plugin
+    }
+
+    fn events(&self) -> anyhow::Result<Events<'_>> {
+        match self.receiver.try_lock() {
+            Ok(receiver) => Ok(Events(receiver)),
+            Err(TryLockError::Poisoned(e)) => Ok(Events(e.into_inner())),
+            Err(TryLockError::WouldBlock) => Err(anyhow::anyhow!(
+                "events already taken, drop the Events returned before"
+            )),
+        }
+    }
+}
+
+/// The events received by a node, returned by [LightningD::events].
+///
+/// Iterating blocks until the next event, use [Events::recv_timeout] or [Events::wait_for] to
+/// bound the wait.
+#[derive(Debug)]
+pub struct Events<'a>(MutexGuard<'a, Receiver<Event>>);
+
+impl Events<'_> {
+    /// Return the next event, or `None` if none arrives within `timeout`
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Option<Event> {
+        self.0.recv_timeout(timeout).ok()
+    }
+
+    /// Return the next event already received, without waiting
+    pub fn try_next(&mut self) -> Option<Event> {
+        self.0.try_recv().ok()
+    }
+
+    /// Wait for the first event matching `predicate`, discarding the ones before it
+    pub fn wait_for(
+        &mut self,
+        timeout: Duration,
+        predicate: impl Fn(&Event) -> bool,
+    ) -> anyhow::Result<Event> {
+        let start = Instant::now();
+        loop {
+            let remaining = timeout
+                .checked_sub(start.elapsed())
+                .ok_or_else(|| anyhow::anyhow!("no matching event after {:?}", timeout))?;
+            match self.recv_timeout(remaining) {
+                Some(event) if predicate(&event) => return Ok(event),
+                Some(event) => debug!("skipping event {:?}", event),
+                None => anyhow::bail!("no matching event after {:?}", timeout),
+            }
+        }
+    }
+}
+
+impl Iterator for Events<'_> {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        self.0.recv().ok()
+    }
+}
+
+impl LightningD {
+    /// Return the stream of the notifications of the topics in [crate::Conf::events].
+    ///
+    /// The stream is exclusive, calling it again fails until the returned [Events] is dropped.
+    pub fn events(&self) -> anyhow::Result<Events<'_>> {
+        self.events
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("events requires Conf::events"))?
+            .events()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Connect, Event, EventBridge, InvoicePayment};
+    use crate::{exe_path, Conf, LightningD};
+    use serde_json::json;
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_event() {
+        let params =
+            json!({"invoice_payment": {"label": "l", "preimage": "00", "msat": "1000msat"}});
+        assert_eq!(
+            Event::parse("invoice_payment", &params),
+            Event::InvoicePayment(InvoicePayment {
+                label: "l".to_string(),
+                preimage: "00".to_string(),
+                msat: 1000,
+            })
+        );
+        let params = json!({"id": "02aa", "address": {"type": "ipv4"}});
+        assert_eq!(
+            Event::parse("connect", &params),
+            Event::Connect(Connect {
+                id: "02aa".to_string(),
+                direction: None,
+                address: json!({"type": "ipv4"}),
+            })
+        );
+        let params = json!({"warning": {"level": "warn"}});
+        let event = Event::parse("warning", &params);
+        assert_eq!(event.topic(), "warning");
+        assert_eq!(
+            event,
+            Event::Other {
+                topic: "warning".to_string(),
+                params
+            }
+        );
+    }
+
+    #[test]
+    fn test_event_bridge() {
+        let bridge = EventBridge::start(&["disconnect"]).unwrap();
+        // act as lightningd
+        let mut child = Command::new(bridge.plugin().path())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        let notification =
+            json!({"jsonrpc": "2.0", "method": "disconnect", "params": {"id": "02aa"}});
+        write!(stdin, "{}\n\n", notification).unwrap();
+
+        let mut events = bridge.events().unwrap();
+        let event = events.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(event.topic(), "disconnect");
+        assert!(bridge.events().is_err());
+        drop(events);
+        assert!(bridge.events().unwrap().try_next().is_none());
+        drop(stdin);
+        assert!(child.wait().unwrap().success());
+    }
+
+    #[test]
+    fn test_events() {
+        let _ = env_logger::try_init();
+        let conf = Conf {
+            events: vec!["connect"],
+            ..Default::default()
+        };
+        let alice = LightningD::with_conf(exe_path().unwrap(), &conf).unwrap();
+        let bob = LightningD::new(exe_path().unwrap()).unwrap();
+        assert!(bob.events().is_err());
+        alice.connect(&bob).unwrap();
+        let bob_id = bob.node_id().unwrap();
+        let event = alice
+            .events()
+            .unwrap()
+            .wait_for(
+                Duration::from_secs(10),
+                |e| matches!(e, Event::Connect(c) if c.id == bob_id),
+            )
+            .unwrap();
+        assert_eq!(event.topic(), "connect");
+    }
+}
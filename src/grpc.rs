@@ -0,0 +1,132 @@
+use crate::{poll_until, LightningD, LOCAL_IP};
+use std::net::{SocketAddrV4, TcpStream};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Maximum time waited for `cln-grpc` to listen once lightningd is ready
+const GRPC_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The mTLS certificates generated by `cln-grpc` in the network directory of the node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrpcCerts {
+    /// Certificate authority which signed the server and client certificates, `ca.pem`
+    pub ca: PathBuf,
+    /// Client certificate, `client.pem`
+    pub client: PathBuf,
+    /// Key of the client certificate, `client-key.pem`
+    pub client_key: PathBuf,
+}
+
+impl GrpcCerts {
+    fn in_dir(dir: &Path) -> GrpcCerts {
+        GrpcCerts {
+            ca: dir.join("ca.pem"),
+            client: dir.join("client.pem"),
+            client_key: dir.join("client-key.pem"),
+        }
+    }
+}
+
+/// Wait until `cln-grpc` generated the certificates in `dir` and listens on `port`
+pub(crate) fn wait_grpc(dir: &Path, port: u16) -> anyhow::Result<()> {
+    let certs = GrpcCerts::in_dir(dir);
+    poll_until(GRPC_TIMEOUT, || {
+        let generated = [&certs.ca, &certs.client, &certs.client_key]
+            .iter()
+            .all(|p| p.exists());
+        Ok(Some(())
+            .filter(|_| generated && TcpStream::connect(SocketAddrV4::new(LOCAL_IP, port)).is_ok()))
+    })?
+    .ok_or_else(|| {
+        anyhow::anyhow!(
+            "cln-grpc not listening on port {} after {:?}",
+            port,
+            GRPC_TIMEOUT
+        )
+    })
+}
+
+/// Methods requiring a node started with [crate::Conf::grpc] enabled
+impl LightningD {
+    /// Return the url of the `cln-grpc` endpoint, like `https://127.0.0.1:9736`
+    pub fn grpc_endpoint(&self) -> Option<String> {
+        self.grpc_port
+            .map(|port| format!("https://{}", SocketAddrV4::new(LOCAL_IP, port)))
+    }
+
+    /// Return the paths of the certificates needed to connect to [LightningD::grpc_endpoint]
+    pub fn grpc_certs(&self) -> Option<GrpcCerts> {
+        let network_dir = self.rpc_path.parent()?;
+        self.grpc_port.map(|_| GrpcCerts::in_dir(network_dir))
+    }
+
+    /// Return a `cln-grpc` client connected to [LightningD::grpc_endpoint]
+    #[cfg(feature = "grpc")]
+    pub async fn grpc_client(
+        &self,
+    ) -> anyhow::Result<cln_grpc::pb::node_client::NodeClient<tonic::transport::Channel>> {
+        use tonic::transport::{Certificate, Channel, ClientTlsConfig, Identity};
+
+        let (endpoint, certs) = match (self.grpc_endpoint(), self.grpc_certs()) {
+            (Some(endpoint), Some(certs)) => (endpoint, certs),
+            _ => anyhow::bail!("grpc requires Conf::grpc"),
+        };
+        let identity = Identity::from_pem(
+            std::fs::read(&certs.client)?,
+            std::fs::read(&certs.client_key)?,
+        );
+        let tls = ClientTlsConfig::new()
+            // the name in the certificate of the server generated by cln-grpc
+            .domain_name("cln")
+            .ca_certificate(Certificate::from_pem(std::fs::read(&certs.ca)?))
+            .identity(identity);
+        let channel = Channel::from_shared(endpoint)?
+            .tls_config(tls)?
+            .connect()
+            .await?;
+        Ok(cln_grpc::pb::node_client::NodeClient::new(channel))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{exe_path, Conf, LightningD};
+
+    #[test]
+    fn test_grpc() {
+        let _ = env_logger::try_init();
+        let conf = Conf {
+            grpc: true,
+            ..Default::default()
+        };
+        let lightningd = LightningD::with_conf(exe_path().unwrap(), &conf).unwrap();
+        assert!(lightningd
+            .grpc_endpoint()
+            .unwrap()
+            .starts_with("https://127.0.0.1:"));
+        let certs = lightningd.grpc_certs().unwrap();
+        assert!(certs.ca.exists() && certs.client.exists() && certs.client_key.exists());
+
+        #[cfg(feature = "grpc")]
+        {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async {
+                let mut client = lightningd.grpc_client().await.unwrap();
+                let info = client
+                    .getinfo(cln_grpc::pb::GetinfoRequest {})
+                    .await
+                    .unwrap()
+                    .into_inner();
+                assert_eq!(hex(&info.id), lightningd.node_id().unwrap());
+            });
+        }
+    }
+
+    #[cfg(feature = "grpc")]
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
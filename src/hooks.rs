@@ -0,0 +1,223 @@
+use crate::plugin::Plugin;
+use crate::LightningD;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Name of the plugin relaying the hooks to the test process
+const PLUGIN_NAME: &str = "hooks-bridge";
+
+/// A hook registered by the node on startup with [crate::Conf::hooks], decided by the closure
+/// set with [LightningD::on_hook]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Hook {
+    /// `htlc_accepted`, to hold, resolve or fail incoming HTLCs
+    HtlcAccepted,
+    /// `openchannel`, to accept or reject channels opened by peers
+    OpenChannel,
+    /// `peer_connected`, to accept or disconnect peers
+    PeerConnected,
+    /// `rpc_command`, to intercept the json-rpc calls to the node
+    RpcCommand,
+    /// `custommsg`, to receive the custom messages sent by peers
+    CustomMsg,
+}
+
+impl Hook {
+    /// Name of the hook in lightningd
+    pub fn name(self) -> &'static str {
+        match self {
+            Hook::HtlcAccepted => "htlc_accepted",
+            Hook::OpenChannel => "openchannel",
+            Hook::PeerConnected => "peer_connected",
+            Hook::RpcCommand => "rpc_command",
+            Hook::CustomMsg => "custommsg",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Hook> {
+        [
+            Hook::HtlcAccepted,
+            Hook::OpenChannel,
+            Hook::PeerConnected,
+            Hook::RpcCommand,
+            Hook::CustomMsg,
+        ]
+        .iter()
+        .copied()
+        .find(|hook| hook.name() == name)
+    }
+}
+
+/// Decides a hook call given its payload, returning the response to lightningd
+type Decide = Arc<dyn Fn(&Value) -> Value + Send + Sync>;
+
+/// The plugin registering the hooks and the closures deciding them
+pub(crate) struct HookBridge {
+    plugin: Plugin,
+    hooks: Vec<Hook>,
+    decide: Arc<Mutex<HashMap<Hook, Decide>>>,
+}
+
+impl std::fmt::Debug for HookBridge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HookBridge")
+            .field("plugin", &self.plugin)
+            .field("hooks", &self.hooks)
+            .finish()
+    }
+}
+
+impl HookBridge {
+    /// Start the plugin registering `hooks`, answered with `{"result": "continue"}` until a
+    /// closure is set
+    pub(crate) fn start(hooks: &[Hook]) -> anyhow::Result<HookBridge> {
+        let names: Vec<_> = hooks.iter().map(|h| json!({ "name": h.name() })).collect();
+        let manifest = json!({
+            "options": [],
+            "rpcmethods": [],
+            "hooks": names,
+            "dynamic": false,
+        });
+        let decide: Arc<Mutex<HashMap<Hook, Decide>>> = Arc::default();
+        let plugin = {
+            let decide = decide.clone();
+            Plugin::start(
+                PLUGIN_NAME,
+                manifest,
+                Arc::new(move |method, params| {
+                    // not holding the lock while deciding, closures could wait for the test
+                    let f = Hook::from_name(method)
+                        .and_then(|hook| decide.lock().unwrap().get(&hook).cloned());
+                    Ok(match f {
+                        Some(f) => f(params),
+                        None => json!({ "result": "continue" }),
+                    })
+                }),
+            )?
+        };
+        Ok(HookBridge {
+            plugin,
+            hooks: hooks.to_vec(),
+            decide,
+        })
+    }
+
+    pub(crate) fn plugin(&self) -> &Plugin {
+        &self.plugin
+    }
+}
+
+/// Methods requiring a node started with [crate::Conf::hooks]
+impl LightningD {
+    /// Decide the calls of `hook` with `f`, which receives the hook payload and returns the
+    /// response, like `{"result": "continue"}`, replacing the closure set before.
+    ///
+    /// Calls are decided concurrently, `f` could block to hold an HTLC until the test releases
+    /// it. The hook must be listed in [crate::Conf::hooks].
+    pub fn on_hook<F>(&self, hook: Hook, f: F) -> anyhow::Result<()>
+    where
+        F: Fn(&Value) -> Value + Send + Sync + 'static,
+    {
+        let bridge = self.hook_bridge(hook)?;
+        bridge.decide.lock().unwrap().insert(hook, Arc::new(f));
+        Ok(())
+    }
+
+    /// Remove the closure set with [LightningD::on_hook], calls of `hook` continue by default
+    pub fn clear_hook(&self, hook: Hook) -> anyhow::Result<()> {
+        let bridge = self.hook_bridge(hook)?;
+        bridge.decide.lock().unwrap().remove(&hook);
+        Ok(())
+    }
+
+    fn hook_bridge(&self, hook: Hook) -> anyhow::Result<&HookBridge> {
+        self.hooks
+            .as_ref()
+            .filter(|bridge| bridge.hooks.contains(&hook))
+            .ok_or_else(|| anyhow::anyhow!("{} not registered in Conf::hooks", hook.name()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Hook, HookBridge};
+    use crate::{exe_path, Conf, LightningD};
+    use serde_json::{json, Deserializer, Value};
+    use std::collections::HashMap;
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+    use std::sync::{mpsc, Arc, Mutex};
+
+    #[test]
+    fn test_hook_bridge() {
+        let bridge = HookBridge::start(&[Hook::HtlcAccepted]).unwrap();
+        let (release, released) = mpsc::channel::<()>();
+        let released = Mutex::new(released);
+        bridge.decide.lock().unwrap().insert(
+            Hook::HtlcAccepted,
+            Arc::new(move |payload: &Value| {
+                if payload["htlc"]["id"] == 0 {
+                    // hold the first htlc until the second is decided
+                    released.lock().unwrap().recv().unwrap();
+                    json!({"result": "fail", "failure_message": "2002"})
+                } else {
+                    json!({"result": "continue"})
+                }
+            }),
+        );
+
+        // act as lightningd
+        let mut child = Command::new(bridge.plugin().path())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        for id in 0..2 {
+            let request = json!({"jsonrpc": "2.0", "id": id, "method": "htlc_accepted", "params": {"htlc": {"id": id}}});
+            write!(stdin, "{}\n\n", request).unwrap();
+        }
+        let stdout = child.stdout.take().unwrap();
+        let mut responses = Deserializer::from_reader(stdout).into_iter::<Value>();
+        let response = responses.next().unwrap().unwrap();
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["result"]["result"], "continue");
+        release.send(()).unwrap();
+        let response = responses.next().unwrap().unwrap();
+        assert_eq!(response["id"], 0);
+        assert_eq!(response["result"]["result"], "fail");
+
+        drop(stdin);
+        assert!(child.wait().unwrap().success());
+    }
+
+    #[test]
+    fn test_hooks() {
+        let _ = env_logger::try_init();
+        let conf = Conf {
+            hooks: vec![Hook::PeerConnected],
+            ..Default::default()
+        };
+        let alice = LightningD::new(exe_path().unwrap()).unwrap();
+        let bob = LightningD::with_conf(exe_path().unwrap(), &conf).unwrap();
+        assert!(bob.on_hook(Hook::OpenChannel, |_| json!({})).is_err());
+
+        let seen = Arc::new(Mutex::new(HashMap::new()));
+        let seen_by_hook = seen.clone();
+        bob.on_hook(Hook::PeerConnected, move |payload| {
+            let id = payload["peer"]["id"]
+                .as_str()
+                .unwrap_or_default()
+                .to_string();
+            *seen_by_hook.lock().unwrap().entry(id).or_insert(0) += 1;
+            json!({"result": "disconnect", "error_message": "go away"})
+        })
+        .unwrap();
+        assert!(alice.connect(&bob).is_err());
+        assert!(seen.lock().unwrap().contains_key(&alice.node_id().unwrap()));
+
+        bob.clear_hook(Hook::PeerConnected).unwrap();
+        alice.connect(&bob).unwrap();
+    }
+}
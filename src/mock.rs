@@ -0,0 +1,257 @@
+use crate::cassette::Replay;
+use crate::server::RpcServer;
+use clightningrpc::LightningRPC;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Json-rpc error code returned by lightningd for unknown methods
+const UNKNOWN_COMMAND: i64 = -32601;
+
+/// A json-rpc error returned by [MockLightningD]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RpcError {
+    /// Error code, like `-32601` for unknown methods
+    pub code: i64,
+    /// Human readable message
+    pub message: String,
+    /// Additional details, like the attempts of a failed `pay`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl RpcError {
+    /// An error with the given `code` and `message` and no data
+    pub fn new(code: i64, message: &str) -> Self {
+        RpcError {
+            code,
+            message: message.to_string(),
+            data: None,
+        }
+    }
+}
+
+/// A json-rpc request received by [MockLightningD]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RpcCall {
+    /// Called method
+    pub method: String,
+    /// Params of the call, an object or an array
+    pub params: Value,
+}
+
+type Handler = Box<dyn FnMut(&Value) -> Result<Value, RpcError> + Send>;
+
+#[derive(Default)]
+struct State {
+    once: HashMap<String, VecDeque<Result<Value, RpcError>>>,
+    handlers: HashMap<String, Handler>,
+    calls: Vec<RpcCall>,
+    replay: Option<Replay>,
+}
+
+impl State {
+    fn handle(&mut self, method: &str, params: &Value) -> Result<Value, RpcError> {
+        self.calls.push(RpcCall {
+            method: method.to_string(),
+            params: params.clone(),
+        });
+        if let Some(result) = self.once.get_mut(method).and_then(|q| q.pop_front()) {
+            return result;
+        }
+        if let Some(handler) = self.handlers.get_mut(method) {
+            return handler(params);
+        }
+        if let Some(replay) = self.replay.as_mut() {
+            return replay.handle(method, params);
+        }
+        Err(RpcError::new(
+            UNKNOWN_COMMAND,
+            &format!("Unknown command '{}'", method),
+        ))
+    }
+}
+
+/// An in-process fake lightningd serving scripted json-rpc responses on a unix socket.
+///
+/// Any [LightningRPC] pointed at [MockLightningD::rpc_path], like the one returned by
+/// [MockLightningD::client], talks to the mock instead of a real node. Every received call is
+/// recorded and could be inspected with [MockLightningD::calls].
+///
+/// `getinfo` and `listfunds` have a default response, which could be overridden like any other
+/// method.
+///
+/// ```
+/// let mock = lightningd::MockLightningD::new().unwrap();
+/// mock.respond("newaddr", lightningd::serde_json::json!({"bech32": "bcrt1q..."}));
+/// let address = mock.client().newaddr(None).unwrap();
+/// assert_eq!(address.bech32.unwrap(), "bcrt1q...");
+/// assert_eq!(mock.calls()[0].method, "newaddr");
+/// ```
+pub struct MockLightningD {
+    server: RpcServer,
+    state: Arc<Mutex<State>>,
+}
+
+impl std::fmt::Debug for MockLightningD {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MockLightningD")
+            .field("rpc_path", &self.server.rpc_path())
+            .finish()
+    }
+}
+
+impl MockLightningD {
+    /// Start serving on a socket in a new temporary directory
+    pub fn new() -> anyhow::Result<MockLightningD> {
+        let mock = MockLightningD::with_state(State::default())?;
+        mock.respond("getinfo", default_getinfo());
+        mock.respond("listfunds", json!({"outputs": [], "channels": []}));
+        Ok(mock)
+    }
+
+    /// Serve the interactions of the `cassette` recorded with [crate::Conf::record], in order.
+    ///
+    /// Every request must match the method and params of the next recorded interaction, otherwise
+    /// an error is returned to the client and [MockLightningD::check_replayed] fails. The labels
+    /// generated by [crate::LightningD::create_invoice] are not matched, since they are unique to
+    /// every run.
+    ///
+    /// Consecutive identical requests, like the ones of the polling helpers, are replayed with the
+    /// last recorded response, however many times they are repeated, so two identical calls in a
+    /// row can't get different responses. There are no default responses, but the ones set with
+    /// [MockLightningD::respond] and the like take precedence over the cassette.
+    pub fn replay<P: AsRef<Path>>(cassette: P) -> anyhow::Result<MockLightningD> {
+        let replay = Replay::load(cassette.as_ref())?;
+        MockLightningD::with_state(State {
+            replay: Some(replay),
+            ..Default::default()
+        })
+    }
+
+    fn with_state(state: State) -> anyhow::Result<MockLightningD> {
+        let state = Arc::new(Mutex::new(state));
+        let server = {
+            let state = state.clone();
+            RpcServer::start(
+                "lightning-rpc",
+                Arc::new(move |method, params| state.lock().unwrap().handle(method, params)),
+            )?
+        };
+        Ok(MockLightningD { server, state })
+    }
+
+    /// Path of the json-rpc unix socket
+    pub fn rpc_path(&self) -> PathBuf {
+        self.server.rpc_path().to_path_buf()
+    }
+
+    /// A new rpc client connected to this mock
+    pub fn client(&self) -> LightningRPC {
+        LightningRPC::new(self.server.rpc_path())
+    }
+
+    /// Always respond to `method` with `result`
+    pub fn respond(&self, method: &str, result: Value) {
+        self.respond_with(method, move |_| Ok(result.clone()));
+    }
+
+    /// Always respond to `method` with the given error
+    pub fn respond_error(&self, method: &str, code: i64, message: &str) {
+        let error = RpcError::new(code, message);
+        self.respond_with(method, move |_| Err(error.clone()));
+    }
+
+    /// Respond to `method` computing the result from the call params
+    pub fn respond_with<F>(&self, method: &str, handler: F)
+    where
+        F: FnMut(&Value) -> Result<Value, RpcError> + Send + 'static,
+    {
+        let mut state = self.state.lock().unwrap();
+        state.handlers.insert(method.to_string(), Box::new(handler));
+    }
+
+    /// Respond to the next call to `method` with `result`, before falling back to the responses
+    /// set with [MockLightningD::respond]. Multiple calls are queued.
+    pub fn respond_once(&self, method: &str, result: Result<Value, RpcError>) {
+        let mut state = self.state.lock().unwrap();
+        state
+            .once
+            .entry(method.to_string())
+            .or_default()
+            .push_back(result);
+    }
+
+    /// All the calls received so far, in order
+    pub fn calls(&self) -> Vec<RpcCall> {
+        self.state.lock().unwrap().calls.clone()
+    }
+
+    /// Params of the calls to `method` received so far, in order
+    pub fn calls_to(&self, method: &str) -> Vec<Value> {
+        self.calls()
+            .into_iter()
+            .filter(|c| c.method == method)
+            .map(|c| c.params)
+            .collect()
+    }
+
+    /// Check a mock created with [MockLightningD::replay] received no unexpected request and
+    /// replayed the whole cassette
+    pub fn check_replayed(&self) -> anyhow::Result<()> {
+        match self.state.lock().unwrap().replay.as_ref() {
+            Some(replay) => replay.check(),
+            None => Err(anyhow::anyhow!("not replaying a cassette")),
+        }
+    }
+}
+
+pub(crate) fn default_getinfo() -> Value {
+    json!({
+        "id": "02eec7245d6b7d2ccb30380bfbe2a3648cd7a942653f5aa340edcea1f283686619",
+        "alias": "MOCK",
+        "color": "02eec7",
+        "num_peers": 0,
+        "num_pending_channels": 0,
+        "num_active_channels": 0,
+        "num_inactive_channels": 0,
+        "address": [],
+        "binding": [],
+        "version": "v23.05.2",
+        "blockheight": 0,
+        "network": "regtest",
+        "fees_collected_msat": 0,
+        "lightning-dir": "/tmp/mock/regtest",
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MockLightningD, RpcError};
+    use serde_json::{json, Value};
+
+    #[test]
+    fn test_mock() {
+        let mock = MockLightningD::new().unwrap();
+        let client = mock.client();
+        assert_eq!(client.getinfo().unwrap().alias, "MOCK");
+        assert!(client.listfunds().unwrap().outputs.is_empty());
+        assert!(client.call::<_, Value>("notamethod", json!([])).is_err());
+
+        mock.respond_with("echo", |params| Ok(params.clone()));
+        let echo: Value = client.call("echo", json!({"a": 1})).unwrap();
+        assert_eq!(echo, json!({"a": 1}));
+
+        mock.respond("stop", json!("Shutdown complete"));
+        mock.respond_once("stop", Err(RpcError::new(-1, "busy")));
+        assert!(client.stop().is_err());
+        assert_eq!(client.stop().unwrap(), "Shutdown complete");
+
+        let calls = mock.calls();
+        assert_eq!(calls.len(), 6);
+        assert_eq!(calls[0].method, "getinfo");
+        assert_eq!(mock.calls_to("echo"), vec![json!({"a": 1})]);
+    }
+}
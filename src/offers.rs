@@ -0,0 +1,143 @@
+use crate::{LightningD, Payment};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+/// A BOLT12 offer created with [LightningD::offer]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Offer {
+    /// Id of the offer
+    pub offer_id: String,
+    /// The bolt12 encoded offer, starting with `lno`
+    pub bolt12: String,
+}
+
+/// A BOLT12 invoice request created with [LightningD::invoice_request], to be answered by the
+/// payee with [LightningD::send_invoice]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct InvoiceRequest {
+    /// Id of the invoice request
+    pub invreq_id: String,
+    /// The bolt12 encoded invoice request, starting with `lnr`
+    pub bolt12: String,
+}
+
+fn amount(amount_msat: Option<u64>) -> Value {
+    match amount_msat {
+        Some(amount_msat) => json!(format!("{}msat", amount_msat)),
+        None => json!("any"),
+    }
+}
+
+/// Methods requiring a node started with [crate::Conf::offers] enabled
+impl LightningD {
+    /// Create an offer of `amount_msat`, or of any amount if `None`
+    pub fn offer(&self, amount_msat: Option<u64>, description: &str) -> anyhow::Result<Offer> {
+        let result = self.call("offer", json!([amount(amount_msat), description]))?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    /// Request an invoice for the `offer` to its issuer, returning the bolt12 encoded invoice.
+    ///
+    /// `amount_msat` is required only if the offer doesn't specify one. The invoice could be paid
+    /// with [LightningD::pay_invoice].
+    pub fn fetch_invoice(&self, offer: &str, amount_msat: Option<u64>) -> anyhow::Result<String> {
+        let params = match amount_msat {
+            Some(amount_msat) => json!([offer, amount_msat]),
+            None => json!([offer]),
+        };
+        let result = self.call("fetchinvoice", params)?;
+        result["invoice"]
+            .as_str()
+            .map(ToString::to_string)
+            .ok_or_else(|| anyhow::anyhow!("missing invoice in {}", result))
+    }
+
+    /// Fetch an invoice for the `offer` and pay it, waiting for the payment to complete
+    pub fn pay_offer(&self, offer: &str, amount_msat: Option<u64>) -> anyhow::Result<Payment> {
+        let invoice = self.fetch_invoice(offer, amount_msat)?;
+        self.pay_invoice(&invoice)
+    }
+
+    /// Create an invoice request to be paid `amount_msat`, like a withdrawal offer
+    pub fn invoice_request(
+        &self,
+        amount_msat: u64,
+        description: &str,
+    ) -> anyhow::Result<InvoiceRequest> {
+        let result = self.call("invoicerequest", json!([amount(Some(amount_msat)), description]))?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    /// Answer the `invreq` invoice request with an invoice labeled `label`, waiting for the issuer
+    /// of the request to pay it. Returns the raw `sendinvoice` result.
+    pub fn send_invoice(&self, invreq: &str, label: &str) -> anyhow::Result<Value> {
+        self.call("sendinvoice", json!([invreq, label]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{exe_path, Conf, LightningD};
+
+    #[test]
+    fn test_offers() {
+        let _ = env_logger::try_init();
+        let exe = exe_path().unwrap();
+        let conf = Conf {
+            offers: true,
+            ..Default::default()
+        };
+        let alice = LightningD::with_conf(&exe, &conf).unwrap();
+        let bob = LightningD::with_conf(&exe, &conf).unwrap();
+        alice.sync();
+        bob.sync();
+        bob.connect(&alice).unwrap();
+
+        let offer = alice.offer(Some(10_000), "coffee").unwrap();
+        assert!(offer.bolt12.starts_with("lno"));
+        let invoice = bob.fetch_invoice(&offer.bolt12, None).unwrap();
+        assert!(invoice.starts_with("lni"));
+
+        let any = alice.offer(None, "tip").unwrap();
+        assert!(bob.fetch_invoice(&any.bolt12, None).is_err());
+        assert!(bob.fetch_invoice(&any.bolt12, Some(5_000)).is_ok());
+
+        let invreq = bob.invoice_request(20_000, "withdrawal").unwrap();
+        assert!(invreq.bolt12.starts_with("lnr"));
+    }
+
+    #[cfg(feature = "bitcoin_backend")]
+    #[test]
+    fn test_pay_offer() {
+        use crate::chain::open_channel;
+        use crate::FakeChain;
+        use serde_json::json;
+
+        let _ = env_logger::try_init();
+        let exe = exe_path().unwrap();
+        let conf = Conf {
+            offers: true,
+            fake_chain: Some(FakeChain::new()),
+            ..Default::default()
+        };
+        let alice = LightningD::with_conf(&exe, &conf).unwrap();
+        let bob = LightningD::with_conf(&exe, &conf).unwrap();
+        open_channel(&bob, &alice, 500_000).unwrap();
+
+        let offer = alice.offer(Some(10_000), "coffee").unwrap();
+        let payment = bob.pay_offer(&offer.bolt12, None).unwrap();
+        assert_eq!(payment.amount_msat, 10_000);
+        let paid = alice.call("listinvoices", json!({})).unwrap();
+        assert_eq!(paid["invoices"][0]["status"], "paid");
+        assert_eq!(
+            paid["invoices"][0]["local_offer_id"],
+            offer.offer_id.as_str()
+        );
+
+        // alice withdraws from bob, answering his invoice request
+        let invreq = bob.invoice_request(20_000, "withdrawal").unwrap();
+        let invoice = alice.send_invoice(&invreq.bolt12, "withdrawal").unwrap();
+        assert_eq!(invoice["status"], "paid");
+        assert_eq!(invoice["label"], "withdrawal");
+    }
+}
@@ -0,0 +1,327 @@
+use crate::{poll_until, LightningD};
+use anyhow::Context;
+use serde::{Deserialize, Deserializer};
+use serde_json::{json, Value};
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+static LABEL_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Prefix of the labels generated by [LightningD::create_invoice]
+const LABEL_PREFIX: &str = "lightningd-";
+
+/// Whether `s` is a label generated by [LightningD::create_invoice], like `lightningd-<nanos>-<n>`
+pub(crate) fn is_generated_label(s: &str) -> bool {
+    let mut parts = match s.strip_prefix(LABEL_PREFIX) {
+        Some(rest) => rest.split('-'),
+        None => return false,
+    };
+    let numeric = |part: Option<&str>| {
+        part.is_some_and(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()))
+    };
+    numeric(parts.next()) && numeric(parts.next()) && parts.next().is_none()
+}
+
+/// An invoice created with [LightningD::create_invoice]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Invoice {
+    /// Unique label of the invoice, generated by [LightningD::create_invoice]
+    #[serde(default)]
+    pub label: String,
+    /// The bolt11 encoded invoice to pay
+    pub bolt11: String,
+    /// Hash of the preimage released when the invoice is paid
+    pub payment_hash: String,
+    /// Unix time at which the invoice expires
+    pub expires_at: u64,
+}
+
+/// Outcome of a successful payment made with [LightningD::pay_invoice] or [LightningD::keysend]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Payment {
+    /// Hash of the payment preimage
+    pub payment_hash: String,
+    /// Preimage released by the payee, proof of payment
+    pub payment_preimage: String,
+    /// Amount delivered to the payee
+    #[serde(deserialize_with = "msat")]
+    pub amount_msat: u64,
+    /// Amount sent by the payer, including fees
+    #[serde(deserialize_with = "msat")]
+    pub amount_sent_msat: u64,
+    /// Number of parts the payment has been split into
+    #[serde(default = "one")]
+    pub parts: u32,
+}
+
+impl Payment {
+    /// Fees paid to route the payment
+    pub fn fees_msat(&self) -> u64 {
+        self.amount_sent_msat - self.amount_msat
+    }
+}
+
+/// An invoice paid, returned by [LightningD::wait_invoice_paid]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "RawPaidInvoice")]
+pub struct PaidInvoice {
+    /// Label of the invoice
+    pub label: String,
+    /// Hash of the payment preimage
+    pub payment_hash: String,
+    /// Preimage released to the payer
+    pub payment_preimage: String,
+    /// Amount received, could be more than the invoice amount
+    pub amount_received_msat: u64,
+    /// Unix time at which the invoice has been paid
+    pub paid_at: u64,
+}
+
+/// [PaidInvoice] as returned by `listinvoices`, where versions before v23.02 have both
+/// `amount_received_msat` and the deprecated `msatoshi_received`, and older ones only the latter
+#[derive(Deserialize)]
+struct RawPaidInvoice {
+    label: String,
+    payment_hash: String,
+    payment_preimage: String,
+    amount_received_msat: Option<Value>,
+    msatoshi_received: Option<Value>,
+    paid_at: u64,
+}
+
+impl TryFrom<RawPaidInvoice> for PaidInvoice {
+    type Error = serde_json::Error;
+
+    fn try_from(raw: RawPaidInvoice) -> Result<Self, Self::Error> {
+        let amount = raw
+            .amount_received_msat
+            .or(raw.msatoshi_received)
+            .ok_or_else(|| serde::de::Error::missing_field("amount_received_msat"))?;
+        Ok(PaidInvoice {
+            label: raw.label,
+            payment_hash: raw.payment_hash,
+            payment_preimage: raw.payment_preimage,
+            amount_received_msat: msat(amount)?,
+            paid_at: raw.paid_at,
+        })
+    }
+}
+
+fn one() -> u32 {
+    1
+}
+
+/// Deserialize an amount expressed either as a number or as a string like `1000msat`, used by
+/// older versions
+pub(crate) fn msat<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    match Value::deserialize(deserializer)? {
+        Value::Number(n) => n
+            .as_u64()
+            .ok_or_else(|| serde::de::Error::custom(format!("invalid amount {}", n))),
+        Value::String(s) => s
+            .trim_end_matches("msat")
+            .parse()
+            .map_err(|_| serde::de::Error::custom(format!("invalid amount {}", s))),
+        v => Err(serde::de::Error::custom(format!("invalid amount {}", v))),
+    }
+}
+
+impl LightningD {
+    /// Create an invoice of `amount_msat` with a unique label
+    pub fn create_invoice(&self, amount_msat: u64, description: &str) -> anyhow::Result<Invoice> {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+        let counter = LABEL_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let label = format!("{}{}-{}", LABEL_PREFIX, nanos, counter);
+        let result = self.call("invoice", json!([amount_msat, label, description]))?;
+        let mut invoice: Invoice = serde_json::from_value(result)?;
+        invoice.label = label;
+        Ok(invoice)
+    }
+
+    /// Pay the `bolt11` invoice, waiting for the payment to complete
+    pub fn pay_invoice(&self, bolt11: &str) -> anyhow::Result<Payment> {
+        let result = self
+            .call("pay", json!([bolt11]))
+            .with_context(|| self.channels_dump())?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    /// Spontaneously pay `amount_msat` to the node `destination`, waiting for the payment to
+    /// complete
+    pub fn keysend(&self, destination: &str, amount_msat: u64) -> anyhow::Result<Payment> {
+        let result = self
+            .call("keysend", json!([destination, amount_msat]))
+            .with_context(|| self.channels_dump())?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    /// Wait until the invoice with `label` is paid.
+    ///
+    /// In case of `timeout`, the returned error contains the channels state of this node and of
+    /// the `payer` node.
+    pub fn wait_invoice_paid(
+        &self,
+        label: &str,
+        payer: &LightningD,
+        timeout: Duration,
+    ) -> anyhow::Result<PaidInvoice> {
+        poll_until(timeout, || {
+            let result = self.call("listinvoices", json!([label]))?;
+            let invoice = &result["invoices"][0];
+            match invoice["status"].as_str() {
+                Some("paid") => Ok(Some(serde_json::from_value(invoice.clone())?)),
+                Some("expired") => anyhow::bail!("invoice {} expired", label),
+                None => anyhow::bail!("invoice {} not found", label),
+                _ => Ok(None),
+            }
+        })?
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "invoice {} not paid after {:?}\npayee {}\npayer {}",
+                label,
+                timeout,
+                self.channels_dump(),
+                payer.channels_dump()
+            )
+        })
+    }
+
+    /// Return the channels of this node with their state, as returned by `listpeerchannels`, or
+    /// by `listpeers` on versions not supporting it
+    pub fn channels(&self) -> anyhow::Result<Value> {
+        match self.call("listpeerchannels", json!({})) {
+            Ok(result) => Ok(result["channels"].clone()),
+            Err(_) => {
+                let result = self.call("listpeers", json!({}))?;
+                let channels = result["peers"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .flat_map(|peer| peer["channels"].as_array().cloned().unwrap_or_default())
+                    .collect();
+                Ok(Value::Array(channels))
+            }
+        }
+    }
+
+    /// Human readable dump of the node channels, used in diagnostic messages
+    fn channels_dump(&self) -> String {
+        let id = self
+            .call("getinfo", json!({}))
+            .map(|info| info["id"].as_str().unwrap_or_default().to_string())
+            .unwrap_or_default();
+        let channels = self
+            .channels()
+            .and_then(|c| Ok(serde_json::to_string_pretty(&c)?))
+            .unwrap_or_else(|e| format!("cannot list channels: {:?}", e));
+        format!("node {} channels: {}", id, channels)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{is_generated_label, PaidInvoice, Payment};
+    use serde_json::json;
+
+    #[test]
+    fn test_generated_label() {
+        assert!(is_generated_label("lightningd-1700000000000000000-0"));
+        assert!(!is_generated_label("lightningd-1700000000000000000"));
+        assert!(!is_generated_label("lightningd-1-a"));
+        assert!(!is_generated_label("lightningd--1"));
+        assert!(!is_generated_label("label"));
+    }
+
+    #[test]
+    fn test_deserialize_amounts() {
+        let pay = json!({
+            "destination": "02aa",
+            "payment_hash": "aa",
+            "created_at": 1.0,
+            "parts": 2,
+            "amount_msat": 1000,
+            "amount_sent_msat": 1010,
+            "payment_preimage": "bb",
+            "status": "complete"
+        });
+        let payment: Payment = serde_json::from_value(pay).unwrap();
+        assert_eq!(payment.parts, 2);
+        assert_eq!(payment.fees_msat(), 10);
+
+        // older versions use strings with the unit
+        let pay = json!({
+            "payment_hash": "aa",
+            "amount_msat": "1000msat",
+            "amount_sent_msat": "1001msat",
+            "payment_preimage": "bb",
+        });
+        let payment: Payment = serde_json::from_value(pay).unwrap();
+        assert_eq!(payment.parts, 1);
+        assert_eq!(payment.fees_msat(), 1);
+
+        let invoice = json!({
+            "label": "l",
+            "payment_hash": "aa",
+            "payment_preimage": "bb",
+            "msatoshi_received": 1000,
+            "paid_at": 1700000000,
+        });
+        let invoice: PaidInvoice = serde_json::from_value(invoice).unwrap();
+        assert_eq!(invoice.amount_received_msat, 1000);
+
+        // versions before v23.02 have both, the deprecated one as number
+        let invoice = json!({
+            "label": "l",
+            "payment_hash": "aa",
+            "payment_preimage": "bb",
+            "msatoshi_received": 1000,
+            "amount_received_msat": "1001msat",
+            "paid_at": 1700000000,
+        });
+        let invoice: PaidInvoice = serde_json::from_value(invoice).unwrap();
+        assert_eq!(invoice.amount_received_msat, 1001);
+
+        let invoice = json!({
+            "label": "l",
+            "payment_hash": "aa",
+            "payment_preimage": "bb",
+            "paid_at": 1700000000,
+        });
+        assert!(serde_json::from_value::<PaidInvoice>(invoice).is_err());
+    }
+
+    #[cfg(feature = "bitcoin_backend")]
+    #[test]
+    fn test_pay_invoice() {
+        use crate::chain::open_channel;
+        use crate::{exe_path, Conf, FakeChain, LightningD};
+        use std::time::Duration;
+
+        let _ = env_logger::try_init();
+        let conf = Conf {
+            fake_chain: Some(FakeChain::new()),
+            ..Default::default()
+        };
+        let alice = LightningD::with_conf(exe_path().unwrap(), &conf).unwrap();
+        let bob = LightningD::with_conf(exe_path().unwrap(), &conf).unwrap();
+        open_channel(&alice, &bob, 500_000).unwrap();
+
+        let invoice = bob.create_invoice(10_000, "test").unwrap();
+        assert!(invoice.bolt11.starts_with("lnbcrt"));
+        assert_ne!(
+            invoice.label,
+            bob.create_invoice(10_000, "test").unwrap().label
+        );
+        let payment = alice.pay_invoice(&invoice.bolt11).unwrap();
+        assert_eq!(payment.payment_hash, invoice.payment_hash);
+        assert_eq!(payment.amount_msat, 10_000);
+        assert_eq!(payment.fees_msat(), 0);
+
+        let paid = bob
+            .wait_invoice_paid(&invoice.label, &alice, Duration::from_secs(10))
+            .unwrap();
+        assert_eq!(paid.amount_received_msat, 10_000);
+        assert_eq!(paid.payment_preimage, payment.payment_preimage);
+    }
+}
@@ -0,0 +1,114 @@
+use crate::server::{Handle, RpcServer};
+use serde_json::{json, Value};
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// The executable relaying the stdin and stdout of a plugin to `<argv[0]>.sock`, built from
+/// `src/relay.rs` by the build script
+const RELAY: &str = env!("LIGHTNINGD_RELAY");
+
+/// A plugin served by the test process.
+///
+/// lightningd launches [Plugin::path], a link to the relay executable, which connects back to the
+/// socket served here. `getmanifest` is answered with `manifest`, `init` with an empty object and
+/// any other request or notification is passed to `handle`, requests concurrently.
+#[derive(Debug)]
+pub(crate) struct Plugin {
+    _server: RpcServer,
+    path: PathBuf,
+}
+
+impl Plugin {
+    pub(crate) fn start(name: &str, manifest: Value, handle: Handle) -> anyhow::Result<Plugin> {
+        Self::start_with_init(name, manifest, Arc::new(|_| ()), handle)
+    }
+
+    /// Start a plugin passing the `init` params, with the option values, to `init`
+    pub(crate) fn start_with_init(
+        name: &str,
+        manifest: Value,
+        init: Arc<dyn Fn(&Value) + Send + Sync>,
+        handle: Handle,
+    ) -> anyhow::Result<Plugin> {
+        let server = RpcServer::start_concurrent(
+            &format!("{}.sock", name),
+            Arc::new(move |method, params| match method {
+                "getmanifest" => Ok(manifest.clone()),
+                "init" => {
+                    init(params);
+                    Ok(json!({}))
+                }
+                _ => handle(method, params),
+            }),
+        )?;
+        let path = server.rpc_path().with_file_name(name);
+        symlink(RELAY, &path)?;
+        Ok(Plugin {
+            _server: server,
+            path,
+        })
+    }
+
+    /// Path of the executable to pass to lightningd with `--plugin`
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Return the param at `position` or named `name`, since lightningd could pass params either by
+/// position or by name
+#[cfg(feature = "bitcoin_backend")]
+pub(crate) fn param<'a>(params: &'a Value, position: usize, name: &str) -> &'a Value {
+    match params {
+        Value::Array(values) => values.get(position).unwrap_or(&Value::Null),
+        _ => params.get(name).unwrap_or(&Value::Null),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Plugin;
+    use serde_json::{json, Deserializer, Value};
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_relay() {
+        let manifest = json!({"options": [], "rpcmethods": [], "dynamic": false});
+        let plugin = Plugin::start(
+            "echo",
+            manifest.clone(),
+            Arc::new(|_, params| Ok(params.clone())),
+        )
+        .unwrap();
+
+        // act as lightningd
+        let mut child = Command::new(plugin.path())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        let requests = [
+            json!({"jsonrpc": "2.0", "id": 1, "method": "getmanifest", "params": {}}),
+            json!({"jsonrpc": "2.0", "method": "log", "params": {}}),
+            json!({"jsonrpc": "2.0", "id": "cln:2", "method": "echo", "params": [1]}),
+        ];
+        for request in requests.iter() {
+            write!(stdin, "{}\n\n", request).unwrap();
+        }
+        let stdout = child.stdout.take().unwrap();
+        let mut responses = Deserializer::from_reader(stdout).into_iter::<Value>();
+        let response = responses.next().unwrap().unwrap();
+        assert_eq!(response["id"], 1);
+        assert_eq!(response["result"], manifest);
+        let response = responses.next().unwrap().unwrap();
+        assert_eq!(response["id"], "cln:2");
+        assert_eq!(response["result"], json!([1]));
+
+        drop(stdin);
+        assert!(child.wait().unwrap().success());
+    }
+}
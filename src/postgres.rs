@@ -0,0 +1,127 @@
+use crate::get_available_port;
+use anyhow::Context;
+use log::debug;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Env var with the directory of the `initdb` and `pg_ctl` executables, needed when they are not
+/// in the `PATH`, like on Debian where they are in `/usr/lib/postgresql/<version>/bin`
+const BIN_DIR_ENV: &str = "LIGHTNINGD_POSTGRES_BIN";
+
+/// A throwaway PostgreSQL instance, listening on localhost only, stopped when dropped
+#[derive(Debug)]
+pub(crate) struct Postgres {
+    pg_ctl: PathBuf,
+    data_dir: PathBuf,
+    port: u16,
+}
+
+impl Postgres {
+    /// Start an instance with the data in `dir/postgres`, creating the cluster unless it exists
+    /// already, like in a persistent work dir
+    pub(crate) fn start(dir: &Path) -> anyhow::Result<Postgres> {
+        let pg_ctl = executable("pg_ctl")?;
+        let data_dir = dir.join("postgres");
+        if !data_dir.join("PG_VERSION").exists() {
+            let mut initdb = Command::new(executable("initdb")?);
+            initdb
+                .arg("--pgdata")
+                .arg(&data_dir)
+                .args(["--username=postgres", "--auth=trust", "--encoding=UTF8"])
+                .arg("--no-sync");
+            run(&mut initdb)?;
+        }
+        let port = get_available_port()?;
+        // no fsync and no unix socket, whose path could exceed the length limit
+        let options = format!("-p {} -h 127.0.0.1 -k '' -F", port);
+        let mut start = Command::new(&pg_ctl);
+        start
+            .arg("--pgdata")
+            .arg(&data_dir)
+            .arg("--log")
+            .arg(data_dir.join("postgres.log"))
+            .args(["--options", options.as_str(), "--wait", "start"]);
+        run(&mut start)?;
+        debug!("postgres started in {:?} on port {}", data_dir, port);
+        Ok(Postgres {
+            pg_ctl,
+            data_dir,
+            port,
+        })
+    }
+
+    /// Connection string of the default database, to be passed with `--wallet`
+    pub(crate) fn dsn(&self) -> String {
+        format!("postgres://postgres@127.0.0.1:{}/postgres", self.port)
+    }
+}
+
+impl Drop for Postgres {
+    fn drop(&mut self) {
+        let mut stop = Command::new(&self.pg_ctl);
+        stop.arg("--pgdata")
+            .arg(&self.data_dir)
+            .args(["--mode=immediate", "--wait", "stop"]);
+        if let Err(e) = run(&mut stop) {
+            debug!("cannot stop postgres: {:?}", e);
+        }
+    }
+}
+
+/// Path of the executable `name`, in the dir of [BIN_DIR_ENV] or in the `PATH`
+fn executable(name: &str) -> anyhow::Result<PathBuf> {
+    match env::var_os(BIN_DIR_ENV) {
+        Some(dir) => Ok(Path::new(&dir).join(name)),
+        None => which::which(name)
+            .with_context(|| format!("`{}` not found, set {} or the PATH", name, BIN_DIR_ENV)),
+    }
+}
+
+fn run(command: &mut Command) -> anyhow::Result<()> {
+    let output = command
+        .output()
+        .with_context(|| format!("cannot execute {:?}", command))?;
+    anyhow::ensure!(
+        output.status.success(),
+        "{:?} failed: {}",
+        command,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::Postgres;
+    use crate::{exe_path, Conf, LightningD};
+    use std::net::TcpStream;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_postgres() {
+        let dir = TempDir::new().unwrap();
+        let postgres = Postgres::start(dir.path()).unwrap();
+        let addr = ("127.0.0.1", postgres.port);
+        assert!(TcpStream::connect(addr).is_ok());
+        assert!(postgres.dsn().starts_with("postgres://postgres@127.0.0.1:"));
+        drop(postgres);
+        assert!(TcpStream::connect(addr).is_err());
+    }
+
+    #[test]
+    fn test_lightningd_postgres() {
+        let _ = env_logger::try_init();
+        let conf = Conf {
+            postgres: true,
+            ..Default::default()
+        };
+        let lightningd = LightningD::with_conf(exe_path().unwrap(), &conf).unwrap();
+        assert!(lightningd.wallet_dsn().unwrap().starts_with("postgres://"));
+        assert!(!lightningd
+            .workdir()
+            .join("regtest")
+            .join("lightningd.sqlite3")
+            .exists());
+    }
+}
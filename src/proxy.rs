@@ -0,0 +1,459 @@
+use crate::{LightningD, LOCAL_IP};
+use anyhow::Context;
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddrV4, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Direction of the traffic through a [Proxy]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// From the connecting node to the proxied one
+    Upstream,
+    /// From the proxied node to the connecting one
+    Downstream,
+    /// Both directions
+    Both,
+}
+
+impl Direction {
+    fn includes(self, other: Direction) -> bool {
+        self == Direction::Both || self == other
+    }
+}
+
+/// Faults injected in a direction
+#[derive(Debug, Clone, Default)]
+struct Faults {
+    /// Hold the traffic until released
+    hold: bool,
+    /// Latency added to every chunk of traffic
+    delay: Duration,
+    /// Maximum bytes per second
+    throttle: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    partitioned: bool,
+    upstream: Faults,
+    downstream: Faults,
+    /// Both sockets of the open connections, by id
+    connections: HashMap<u64, (TcpStream, TcpStream)>,
+    next_id: u64,
+}
+
+impl State {
+    fn faults(&self, direction: Direction) -> Faults {
+        match direction {
+            Direction::Downstream => self.downstream.clone(),
+            _ => self.upstream.clone(),
+        }
+    }
+
+    fn update(&mut self, direction: Direction, f: impl Fn(&mut Faults)) {
+        if direction.includes(Direction::Upstream) {
+            f(&mut self.upstream);
+        }
+        if direction.includes(Direction::Downstream) {
+            f(&mut self.downstream);
+        }
+    }
+}
+
+/// A local TCP proxy in front of a node p2p port, injecting network faults in the traffic of the
+/// peers connecting through it.
+///
+/// ```no_run
+/// # use lightningd::{exe_path, Direction, LightningD, Proxy};
+/// # use std::time::Duration;
+/// let alice = LightningD::new(exe_path().unwrap()).unwrap();
+/// let bob = LightningD::new(exe_path().unwrap()).unwrap();
+/// let proxy = Proxy::new(bob.p2p_socket()).unwrap();
+/// alice.connect_through(&bob, &proxy).unwrap();
+/// proxy.delay(Direction::Both, Duration::from_millis(200));
+/// proxy.partition();
+/// proxy.heal();
+/// ```
+pub struct Proxy {
+    addr: SocketAddrV4,
+    target: SocketAddrV4,
+    state: Arc<Mutex<State>>,
+    shutdown: Arc<AtomicBool>,
+    accept: Option<JoinHandle<()>>,
+}
+
+impl std::fmt::Debug for Proxy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Proxy")
+            .field("addr", &self.addr)
+            .field("target", &self.target)
+            .finish()
+    }
+}
+
+impl Proxy {
+    /// Start proxying connections from a new local port to `target`
+    pub fn new(target: SocketAddrV4) -> anyhow::Result<Proxy> {
+        let listener = TcpListener::bind((LOCAL_IP, 0))?;
+        let addr = SocketAddrV4::new(LOCAL_IP, listener.local_addr()?.port());
+        let state = Arc::new(Mutex::new(State::default()));
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let accept = {
+            let state = state.clone();
+            let shutdown = shutdown.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if shutdown.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    match stream {
+                        Ok(stream) => {
+                            if let Err(e) = open(stream, target, &state) {
+                                debug!("proxy connection to {} refused: {:?}", target, e);
+                            }
+                        }
+                        Err(e) => warn!("proxy accept failed: {:?}", e),
+                    }
+                }
+            })
+        };
+
+        Ok(Proxy {
+            addr,
+            target,
+            state,
+            shutdown,
+            accept: Some(accept),
+        })
+    }
+
+    /// Address where the proxy accepts connections
+    pub fn addr(&self) -> SocketAddrV4 {
+        self.addr
+    }
+
+    /// Close the open connections and refuse new ones, until [Proxy::heal]
+    pub fn partition(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.partitioned = true;
+        for (_, (client, server)) in state.connections.drain() {
+            let _ = client.shutdown(Shutdown::Both);
+            let _ = server.shutdown(Shutdown::Both);
+        }
+    }
+
+    /// Remove the partition and all the faults
+    pub fn heal(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.partitioned = false;
+        state.upstream = Faults::default();
+        state.downstream = Faults::default();
+    }
+
+    /// Stop delivering the traffic in `direction`, keeping the connections open, like a link
+    /// losing every packet, until [Proxy::release_traffic].
+    ///
+    /// The traffic is held rather than discarded, like TCP retransmits lost packets: discarding
+    /// bytes would break the encrypted stream between the peers for good.
+    pub fn hold_traffic(&self, direction: Direction) {
+        let mut state = self.state.lock().unwrap();
+        state.update(direction, |faults| faults.hold = true);
+    }
+
+    /// Deliver the traffic held in `direction` by [Proxy::hold_traffic], and the following one
+    pub fn release_traffic(&self, direction: Direction) {
+        let mut state = self.state.lock().unwrap();
+        state.update(direction, |faults| faults.hold = false);
+    }
+
+    /// Deliver the traffic in `direction` after `delay`
+    pub fn delay(&self, direction: Direction, delay: Duration) {
+        let mut state = self.state.lock().unwrap();
+        state.update(direction, |faults| faults.delay = delay);
+    }
+
+    /// Limit the traffic in `direction` to `bytes_per_sec`, or remove the limit if `None`
+    pub fn throttle(&self, direction: Direction, bytes_per_sec: Option<u64>) {
+        let mut state = self.state.lock().unwrap();
+        state.update(direction, |faults| faults.throttle = bytes_per_sec);
+    }
+}
+
+impl Drop for Proxy {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // wake up the accept loop
+        let _ = TcpStream::connect(self.addr);
+        if let Some(accept) = self.accept.take() {
+            let _ = accept.join();
+        }
+        self.partition();
+    }
+}
+
+/// Connect `client` to `target`, spawning the threads pumping the traffic in both directions
+fn open(client: TcpStream, target: SocketAddrV4, state: &Arc<Mutex<State>>) -> anyhow::Result<()> {
+    if state.lock().unwrap().partitioned {
+        let _ = client.shutdown(Shutdown::Both);
+        anyhow::bail!("partitioned");
+    }
+    let server = TcpStream::connect(target).with_context(|| format!("connecting {}", target))?;
+    let id = {
+        let mut state = state.lock().unwrap();
+        let id = state.next_id;
+        state.next_id += 1;
+        state
+            .connections
+            .insert(id, (client.try_clone()?, server.try_clone()?));
+        id
+    };
+    let tracked = Arc::new(Tracked {
+        id,
+        state: state.clone(),
+    });
+    pump(
+        client.try_clone()?,
+        server.try_clone()?,
+        Direction::Upstream,
+        &tracked,
+    );
+    pump(server, client, Direction::Downstream, &tracked);
+    Ok(())
+}
+
+/// A connection shared by its pump threads, forgotten by the proxy when the last one exits
+struct Tracked {
+    id: u64,
+    state: Arc<Mutex<State>>,
+}
+
+impl Tracked {
+    /// Whether the connection is still open, it's not once partitioned
+    fn is_open(&self) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .connections
+            .contains_key(&self.id)
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        self.state.lock().unwrap().connections.remove(&self.id);
+    }
+}
+
+/// Forward the traffic read from `from` to `to`, applying the faults of `direction`.
+///
+/// Chunks are read and timestamped by one thread and written when due by another, so that delays
+/// add latency without reducing the throughput.
+fn pump(mut from: TcpStream, mut to: TcpStream, direction: Direction, tracked: &Arc<Tracked>) {
+    let (sender, receiver) = mpsc::channel::<(Instant, Vec<u8>)>();
+    let reader = tracked.clone();
+    thread::spawn(move || {
+        let mut buf = [0u8; 8192];
+        loop {
+            let n = match from.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            let faults = reader.state.lock().unwrap().faults(direction);
+            if sender
+                .send((Instant::now() + faults.delay, buf[..n].to_vec()))
+                .is_err()
+            {
+                break;
+            }
+        }
+        let _ = from.shutdown(Shutdown::Read);
+    });
+    let writer = tracked.clone();
+    thread::spawn(move || {
+        write_when_due(&receiver, &mut to, direction, &writer);
+        let _ = to.shutdown(Shutdown::Write);
+    });
+}
+
+fn write_when_due(
+    receiver: &Receiver<(Instant, Vec<u8>)>,
+    to: &mut TcpStream,
+    direction: Direction,
+    tracked: &Tracked,
+) {
+    for (due, chunk) in receiver.iter() {
+        let now = Instant::now();
+        if due > now {
+            thread::sleep(due - now);
+        }
+        while tracked.state.lock().unwrap().faults(direction).hold {
+            if !tracked.is_open() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        // not holding the lock while sleeping, it would stall the other direction and the controls
+        let throttle = tracked.state.lock().unwrap().faults(direction).throttle;
+        if let Some(bytes_per_sec) = throttle {
+            let secs = chunk.len() as f64 / bytes_per_sec.max(1) as f64;
+            thread::sleep(Duration::from_secs_f64(secs));
+        }
+        if to.write_all(&chunk).is_err() {
+            return;
+        }
+    }
+}
+
+impl LightningD {
+    /// Connect this node to `other` as a peer through `proxy`, which must be in front of `other`
+    pub fn connect_through(&self, other: &LightningD, proxy: &Proxy) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            proxy.target == other.p2p_socket(),
+            "the proxy is in front of {}, not of {}",
+            proxy.target,
+            other.p2p_socket()
+        );
+        let id = other.node_id()?;
+        self.client
+            .connect(&id, Some(&proxy.addr.to_string()))
+            .with_context(|| format!("cannot connect to {}@{}", id, proxy.addr))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Direction, Proxy};
+    use crate::{exe_path, LightningD, LOCAL_IP};
+    use serde_json::json;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddrV4, TcpListener, TcpStream};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn echo_server() -> SocketAddrV4 {
+        let listener = TcpListener::bind((LOCAL_IP, 0)).unwrap();
+        let addr = SocketAddrV4::new(LOCAL_IP, listener.local_addr().unwrap().port());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                thread::spawn(move || {
+                    let mut reader = stream.try_clone().unwrap();
+                    let _ = std::io::copy(&mut reader, &mut stream);
+                });
+            }
+        });
+        addr
+    }
+
+    fn roundtrip(stream: &mut TcpStream, len: usize) -> std::io::Result<Vec<u8>> {
+        stream.write_all(&vec![7u8; len])?;
+        let mut buf = vec![0u8; len];
+        stream.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    #[test]
+    fn test_proxy() {
+        let proxy = Proxy::new(echo_server()).unwrap();
+        let mut stream = TcpStream::connect(proxy.addr()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+        assert_eq!(roundtrip(&mut stream, 4).unwrap(), vec![7u8; 4]);
+
+        proxy.delay(Direction::Downstream, Duration::from_millis(200));
+        let start = Instant::now();
+        roundtrip(&mut stream, 4).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        proxy.heal();
+        proxy.throttle(Direction::Upstream, Some(20_000));
+        let start = Instant::now();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        roundtrip(&mut stream, 10_000).unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(400));
+        stream
+            .set_read_timeout(Some(Duration::from_millis(500)))
+            .unwrap();
+
+        proxy.heal();
+        proxy.hold_traffic(Direction::Upstream);
+        assert!(roundtrip(&mut stream, 4).is_err());
+        // the held traffic is delivered once released, keeping the stream in sync
+        proxy.release_traffic(Direction::Upstream);
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).unwrap();
+        assert_eq!(roundtrip(&mut stream, 4).unwrap(), vec![7u8; 4]);
+
+        proxy.partition();
+        let mut buf = [0u8; 1];
+        assert_eq!(stream.read(&mut buf).unwrap(), 0);
+        let mut refused = TcpStream::connect(proxy.addr()).unwrap();
+        assert_eq!(refused.read(&mut buf).unwrap_or_default(), 0);
+
+        proxy.heal();
+        let mut stream = TcpStream::connect(proxy.addr()).unwrap();
+        assert_eq!(roundtrip(&mut stream, 4).unwrap(), vec![7u8; 4]);
+        assert_eq!(proxy.state.lock().unwrap().connections.len(), 1);
+
+        // closed connections are forgotten
+        drop(stream);
+        let start = Instant::now();
+        while !proxy.state.lock().unwrap().connections.is_empty() {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn test_throttle_doesnt_block_controls() {
+        let proxy = Proxy::new(echo_server()).unwrap();
+        let mut stream = TcpStream::connect(proxy.addr()).unwrap();
+        proxy.throttle(Direction::Upstream, Some(1_000));
+        // forwarding it takes 2 seconds
+        stream.write_all(&[7u8; 2_000]).unwrap();
+        thread::sleep(Duration::from_millis(200));
+
+        let start = Instant::now();
+        proxy.heal();
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_connect_through() {
+        let _ = env_logger::try_init();
+        let alice = LightningD::new(exe_path().unwrap()).unwrap();
+        let bob = LightningD::new(exe_path().unwrap()).unwrap();
+        let proxy = Proxy::new(bob.p2p_socket()).unwrap();
+        assert!(alice.connect_through(&alice, &proxy).is_err());
+        alice.connect_through(&bob, &proxy).unwrap();
+        let bob_id = bob.node_id().unwrap();
+        let connected = || {
+            let peers = alice.call("listpeers", json!([bob_id])).unwrap();
+            peers["peers"][0]["connected"] == true
+        };
+        assert!(connected());
+
+        proxy.partition();
+        let start = Instant::now();
+        while connected() {
+            assert!(start.elapsed() < Duration::from_secs(10), "still connected");
+            thread::sleep(Duration::from_millis(100));
+        }
+        assert!(alice.connect_through(&bob, &proxy).is_err());
+
+        proxy.heal();
+        alice.connect_through(&bob, &proxy).unwrap();
+        assert!(connected());
+    }
+}
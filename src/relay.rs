@@ -0,0 +1,46 @@
+// This file is compiled by the build script into a standalone executable, so it must only depend
+// on `std`.
+//
+// lightningd runs plugins as child processes talking json-rpc over stdin and stdout. This
+// executable is launched as a plugin and relays its stdin and stdout to the unix socket
+// `<argv[0]>.sock`, where the plugin is served by the test process.
+
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::net::UnixStream;
+use std::{env, process, thread};
+
+fn main() {
+    let plugin = env::args().next().unwrap_or_default();
+    let socket = format!("{}.sock", plugin);
+    let stream = match UnixStream::connect(&socket) {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("cannot connect to {}: {}", socket, e);
+            process::exit(1);
+        }
+    };
+
+    let mut to_socket = stream.try_clone().expect("clone stream");
+    thread::spawn(move || {
+        let _ = copy(&mut io::stdin().lock(), &mut to_socket);
+        // lightningd closed our stdin, it's shutting down
+        let _ = to_socket.shutdown(Shutdown::Write);
+        process::exit(0);
+    });
+
+    let _ = copy(&mut &stream, &mut io::stdout().lock());
+}
+
+/// Like [io::copy], but flushing after every read so messages are not delayed
+fn copy<R: Read, W: Write>(reader: &mut R, writer: &mut W) -> io::Result<()> {
+    let mut buf = [0u8; 8192];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        writer.write_all(&buf[..n])?;
+        writer.flush()?;
+    }
+}
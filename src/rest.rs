@@ -0,0 +1,74 @@
+use crate::{poll_until, LightningD, Version, LOCAL_IP};
+use std::net::{SocketAddrV4, TcpStream};
+use std::time::Duration;
+
+/// First version bundling the `clnrest` plugin and the `createrune` command
+pub(crate) const CLNREST_VERSION: Version = Version::new(23, 8, 0);
+
+/// Maximum time waited for `clnrest` to listen once lightningd is ready
+const REST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Wait until `clnrest` listens on `port`
+pub(crate) fn wait_rest(port: u16) -> anyhow::Result<()> {
+    poll_until(REST_TIMEOUT, || {
+        Ok(TcpStream::connect(SocketAddrV4::new(LOCAL_IP, port)).ok())
+    })?
+    .map(|_| ())
+    .ok_or_else(|| {
+        anyhow::anyhow!(
+            "clnrest not listening on port {} after {:?}",
+            port,
+            REST_TIMEOUT
+        )
+    })
+}
+
+impl LightningD {
+    /// Return the url of the `clnrest` endpoint, like `http://127.0.0.1:3010`, if started with
+    /// [crate::Conf::rest]
+    pub fn rest_url(&self) -> Option<String> {
+        self.rest_port
+            .map(|port| format!("http://{}", SocketAddrV4::new(LOCAL_IP, port)))
+    }
+
+    /// Return the rune created at startup with [crate::Conf::rune_restrictions], to be sent in
+    /// the `Rune` header of the REST requests
+    pub fn rune(&self) -> Option<&str> {
+        self.rune.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{exe_path, Conf, LightningD, RuneRestrictions};
+    use std::io::{Read, Write};
+    use std::net::TcpStream;
+
+    #[test]
+    fn test_rest() {
+        let _ = env_logger::try_init();
+        let conf = Conf {
+            rest: true,
+            rune_restrictions: RuneRestrictions::new().allow_methods(&["getinfo"]),
+            ..Default::default()
+        };
+        let lightningd = LightningD::with_conf(exe_path().unwrap(), &conf).unwrap();
+        let url = lightningd.rest_url().unwrap();
+        let rune = lightningd.rune().unwrap();
+
+        let post = |method: &str| {
+            let mut stream = TcpStream::connect(url.trim_start_matches("http://")).unwrap();
+            write!(
+                stream,
+                "POST /v1/{} HTTP/1.1\r\nHost: localhost\r\nRune: {}\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{{}}",
+                method, rune
+            )
+            .unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        assert!(post("getinfo").starts_with("HTTP/1.1 201"));
+        assert!(!post("listfunds").starts_with("HTTP/1.1 201"));
+    }
+}
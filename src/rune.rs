@@ -0,0 +1,176 @@
+use crate::LightningD;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Errors returned by `checkrune` when the rune doesn't authorize the call: not authorized, not
+/// permitted by a restriction, blacklisted
+const RUNE_REJECTED: std::ops::RangeInclusive<i32> = 1501..=1503;
+
+/// A rune created with [LightningD::create_rune]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Rune {
+    /// The base64 encoded rune
+    pub rune: String,
+    /// Id of the rune, used to blacklist it
+    pub unique_id: String,
+}
+
+/// Restrictions of a rune, all of them must be satisfied for a call to be authorized.
+///
+/// ```
+/// # use lightningd::RuneRestrictions;
+/// let restrictions = RuneRestrictions::new()
+///     .allow_methods(&["getinfo", "listfunds"])
+///     .rate(10);
+/// assert_eq!(
+///     restrictions.as_slice(),
+///     &[vec!["method=getinfo", "method=listfunds"], vec!["rate=10"]]
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuneRestrictions(Vec<Vec<String>>);
+
+impl RuneRestrictions {
+    /// No restrictions, creating an unrestricted rune
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a restriction satisfied by any of the `alternatives`, like `pnameamount_msat<1000`
+    pub fn restriction<S: ToString>(mut self, alternatives: &[S]) -> Self {
+        self.0
+            .push(alternatives.iter().map(ToString::to_string).collect());
+        self
+    }
+
+    /// Allow only calls to one of `methods`
+    pub fn allow_methods(self, methods: &[&str]) -> Self {
+        let alternatives: Vec<_> = methods.iter().map(|m| format!("method={}", m)).collect();
+        self.restriction(&alternatives)
+    }
+
+    /// Deny calls to all of `methods`
+    pub fn deny_methods(self, methods: &[&str]) -> Self {
+        methods
+            .iter()
+            .fold(self, |r, m| r.restriction(&[format!("method/{}", m)]))
+    }
+
+    /// Allow at most `per_minute` calls per minute
+    pub fn rate(self, per_minute: u32) -> Self {
+        self.restriction(&[format!("rate={}", per_minute)])
+    }
+
+    /// Allow calls only after `time`
+    pub fn not_before(self, time: SystemTime) -> Self {
+        self.restriction(&[format!("time>{}", unix_secs(time))])
+    }
+
+    /// Allow calls only before `time`
+    pub fn not_after(self, time: SystemTime) -> Self {
+        self.restriction(&[format!("time<{}", unix_secs(time))])
+    }
+
+    /// Allow calls only from the node with id `node_id`, as with `commando`
+    pub fn id(self, node_id: &str) -> Self {
+        self.restriction(&[format!("id={}", node_id)])
+    }
+
+    /// The restrictions as passed to `createrune`
+    pub fn as_slice(&self) -> &[Vec<String>] {
+        &self.0
+    }
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+impl LightningD {
+    /// Create a rune with `restrictions`, requires v23.08 or newer
+    pub fn create_rune(&self, restrictions: &RuneRestrictions) -> anyhow::Result<Rune> {
+        let result = self.call("createrune", json!([null, restrictions.as_slice()]))?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    /// Check if `rune` authorizes the node `node_id` to call `method` with `params`.
+    ///
+    /// Returns `Ok(false)` if the rune is rejected, an error if the check couldn't be done.
+    pub fn check_rune(
+        &self,
+        rune: &str,
+        node_id: Option<&str>,
+        method: &str,
+        params: Value,
+    ) -> anyhow::Result<bool> {
+        let mut request = json!({ "rune": rune, "method": method, "params": params });
+        if let Some(node_id) = node_id {
+            request["nodeid"] = json!(node_id);
+        }
+        match self.client.call::<_, Value>("checkrune", request) {
+            Ok(result) => Ok(result["valid"] == true),
+            Err(clightningrpc::Error::Rpc(e)) if RUNE_REJECTED.contains(&e.code) => Ok(false),
+            Err(e) => Err(anyhow::Error::new(e).context("rpc call to `checkrune` failed")),
+        }
+    }
+
+    /// Blacklist `rune`, so that it doesn't authorize any call anymore
+    pub fn blacklist_rune(&self, rune: &Rune) -> anyhow::Result<()> {
+        let unique_id: u64 = rune.unique_id.parse()?;
+        self.call("blacklistrune", json!({ "start": unique_id }))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RuneRestrictions;
+    use crate::{exe_path, LightningD};
+    use serde_json::json;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_rune_restrictions() {
+        let restrictions = RuneRestrictions::new()
+            .deny_methods(&["pay", "withdraw"])
+            .not_before(UNIX_EPOCH + Duration::from_secs(1000))
+            .not_after(UNIX_EPOCH + Duration::from_secs(2000))
+            .id("02aa")
+            .restriction(&["pnum=0", "pnum=1"]);
+        assert_eq!(
+            restrictions.as_slice(),
+            &[
+                vec!["method/pay"],
+                vec!["method/withdraw"],
+                vec!["time>1000"],
+                vec!["time<2000"],
+                vec!["id=02aa"],
+                vec!["pnum=0", "pnum=1"],
+            ]
+        );
+        assert!(RuneRestrictions::new().as_slice().is_empty());
+    }
+
+    #[test]
+    fn test_runes() {
+        let _ = env_logger::try_init();
+        let lightningd = LightningD::new(exe_path().unwrap()).unwrap();
+        let id = lightningd.node_id().unwrap();
+        let restrictions = RuneRestrictions::new().allow_methods(&["getinfo"]).id(&id);
+        let rune = lightningd.create_rune(&restrictions).unwrap();
+
+        let check = |method| {
+            lightningd
+                .check_rune(&rune.rune, Some(&id), method, json!({}))
+                .unwrap()
+        };
+        assert!(check("getinfo"));
+        assert!(!check("listfunds"));
+
+        lightningd.blacklist_rune(&rune).unwrap();
+        assert!(!check("getinfo"));
+    }
+}
@@ -0,0 +1,132 @@
+use crate::RpcError;
+use log::{debug, warn};
+use serde_json::{json, Deserializer, Value};
+use std::io::Write;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use tempfile::TempDir;
+
+/// Handles a json-rpc call given its method and params
+pub(crate) type Handle = Arc<dyn Fn(&str, &Value) -> Result<Value, RpcError> + Send + Sync>;
+
+/// A json-rpc server speaking the lightningd protocol on a unix socket named `name` in a new
+/// temporary directory, serving every connection in its own thread.
+///
+/// Notifications, requests without an `id`, are handled but not answered. Servers started with
+/// [RpcServer::start_concurrent] handle every other request in its own thread, otherwise requests
+/// of a connection are handled in order.
+///
+/// The server is stopped when dropped.
+#[derive(Debug)]
+pub(crate) struct RpcServer {
+    rpc_path: PathBuf,
+    shutdown: Arc<AtomicBool>,
+    accept: Option<JoinHandle<()>>,
+    // dropped after the accept thread is joined, since waking it up requires the socket file
+    _dir: TempDir,
+}
+
+impl RpcServer {
+    pub(crate) fn start(name: &str, handle: Handle) -> anyhow::Result<RpcServer> {
+        Self::spawn(name, handle, false)
+    }
+
+    /// Start a server answering the requests concurrently, since a slow one, like a hook waiting
+    /// for the test, must not delay the others
+    pub(crate) fn start_concurrent(name: &str, handle: Handle) -> anyhow::Result<RpcServer> {
+        Self::spawn(name, handle, true)
+    }
+
+    fn spawn(name: &str, handle: Handle, concurrent: bool) -> anyhow::Result<RpcServer> {
+        let dir = TempDir::new()?;
+        let rpc_path = dir.path().join(name);
+        let listener = UnixListener::bind(&rpc_path)?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+
+        let accept = {
+            let shutdown = shutdown.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    if shutdown.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    match stream {
+                        Ok(stream) => {
+                            let handle = handle.clone();
+                            thread::spawn(move || serve(stream, handle, concurrent));
+                        }
+                        Err(e) => warn!("rpc server accept failed: {:?}", e),
+                    }
+                }
+            })
+        };
+
+        Ok(RpcServer {
+            rpc_path,
+            shutdown,
+            accept: Some(accept),
+            _dir: dir,
+        })
+    }
+
+    pub(crate) fn rpc_path(&self) -> &Path {
+        &self.rpc_path
+    }
+}
+
+impl Drop for RpcServer {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // wake up the accept loop
+        let _ = UnixStream::connect(&self.rpc_path);
+        if let Some(accept) = self.accept.take() {
+            let _ = accept.join();
+        }
+    }
+}
+
+/// Serve the json-rpc requests of a connection until it's closed
+fn serve(stream: UnixStream, handle: Handle, concurrent: bool) {
+    let writer = match stream.try_clone() {
+        Ok(writer) => Arc::new(Mutex::new(writer)),
+        Err(_) => return,
+    };
+    for request in Deserializer::from_reader(stream).into_iter::<Value>() {
+        let request = match request {
+            Ok(request) => request,
+            Err(e) => {
+                debug!("rpc connection closed: {:?}", e);
+                return;
+            }
+        };
+        if request.get("id").is_none() {
+            let method = request["method"].as_str().unwrap_or_default();
+            let params = request.get("params").cloned().unwrap_or(Value::Null);
+            let _ = handle(method, &params);
+        } else if concurrent {
+            let handle = handle.clone();
+            let writer = writer.clone();
+            thread::spawn(move || answer(&request, &handle, &writer));
+        } else if !answer(&request, &handle, &writer) {
+            return;
+        }
+    }
+}
+
+/// Handle `request` and write its response, returning false if the connection is closed
+fn answer(request: &Value, handle: &Handle, writer: &Mutex<UnixStream>) -> bool {
+    let method = request["method"].as_str().unwrap_or_default();
+    let params = request.get("params").cloned().unwrap_or(Value::Null);
+    let response = match handle(method, &params) {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": request["id"], "result": result}),
+        Err(error) => json!({"jsonrpc": "2.0", "id": request["id"], "error": error}),
+    };
+    writer
+        .lock()
+        .unwrap()
+        .write_all(format!("{}\n\n", response).as_bytes())
+        .is_ok()
+}
@@ -0,0 +1,133 @@
+// This file is shared between the build script (via `include!`) and the library tests, so it must
+// only depend on `std`, `anyhow` and `pgp`.
+
+use pgp::types::PublicKeyTrait;
+use pgp::{Deserializable, SignedPublicKey, StandaloneSignature};
+
+const BEGIN_KEY: &str = "-----BEGIN PGP PUBLIC KEY BLOCK-----";
+const END_KEY: &str = "-----END PGP PUBLIC KEY BLOCK-----";
+const BEGIN_SIGNATURE: &str = "-----BEGIN PGP SIGNATURE-----";
+const END_SIGNATURE: &str = "-----END PGP SIGNATURE-----";
+
+/// Split concatenated armored blocks, as found in `.asc` files signed by multiple maintainers
+fn armored_blocks<'a>(armored: &'a str, begin: &str, end: &str) -> Vec<&'a str> {
+    let mut blocks = vec![];
+    let mut rest = armored;
+    while let Some(start) = rest.find(begin) {
+        let len = match rest[start..].find(end) {
+            Some(len) => len + end.len(),
+            None => break,
+        };
+        blocks.push(&rest[start..start + len]);
+        rest = &rest[start + len..];
+    }
+    blocks
+}
+
+/// Parse and check the self-signatures of all the public keys armored in `armored`
+pub fn parse_keys(armored: &str) -> anyhow::Result<Vec<SignedPublicKey>> {
+    let mut keys = vec![];
+    for block in armored_blocks(armored, BEGIN_KEY, END_KEY) {
+        let (key, _) = SignedPublicKey::from_string(block)?;
+        key.verify()?;
+        keys.push(key);
+    }
+    Ok(keys)
+}
+
+/// Verify `content` has a valid detached signature in `armored_signatures` made by one of `keys`,
+/// returning the fingerprint of the signing key
+pub fn verify(
+    content: &[u8],
+    armored_signatures: &str,
+    keys: &[SignedPublicKey],
+) -> anyhow::Result<String> {
+    let mut signatures = vec![];
+    for block in armored_blocks(armored_signatures, BEGIN_SIGNATURE, END_SIGNATURE) {
+        for signature in StandaloneSignature::from_string_many(block)?.0 {
+            signatures.push(signature?);
+        }
+    }
+    anyhow::ensure!(!signatures.is_empty(), "no signature found");
+
+    for key in keys {
+        for signature in signatures.iter() {
+            let signed_by_primary = signature.verify(&key.primary_key, content).is_ok();
+            let signed_by_subkey = key
+                .public_subkeys
+                .iter()
+                .any(|subkey| signature.verify(&subkey.key, content).is_ok());
+            if signed_by_primary || signed_by_subkey {
+                return Ok(hex(key.primary_key.fingerprint().as_bytes()));
+            }
+        }
+    }
+    Err(anyhow::anyhow!(
+        "none of the {} signatures is valid and made by the {} known keys",
+        signatures.len(),
+        keys.len()
+    ))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_keys, verify};
+    use pgp::composed::{KeyType, SecretKeyParamsBuilder};
+    use pgp::crypto::hash::HashAlgorithm;
+    use pgp::packet::{SignatureConfig, SignatureType, Subpacket, SubpacketData};
+    use pgp::types::{PublicKeyTrait, SecretKeyTrait};
+    use pgp::{ArmorOptions, SignedSecretKey, StandaloneSignature};
+
+    const SUMS: &str = "f247ce0b9dbd14df529fbeeb6e4ae101a04f0141b95f54c80d87d4696b26af3e  clightning-v23.05.2-Ubuntu-22.04.tar.xz\n";
+
+    fn key(name: &str) -> (SignedSecretKey, String) {
+        let mut rng = rand::thread_rng();
+        let params = SecretKeyParamsBuilder::default()
+            .key_type(KeyType::EdDSALegacy)
+            .can_sign(true)
+            .primary_user_id(format!("{} <{}@example.com>", name, name))
+            .build()
+            .unwrap();
+        let secret = params.generate(&mut rng).unwrap().sign(&mut rng, String::new).unwrap();
+        let public = secret.public_key().sign(&mut rng, &secret, String::new).unwrap();
+        let armored = public.to_armored_string(ArmorOptions::default()).unwrap();
+        (secret, armored)
+    }
+
+    fn sign(key: &SignedSecretKey, content: &[u8]) -> String {
+        let mut config =
+            SignatureConfig::v4(SignatureType::Binary, key.algorithm(), HashAlgorithm::SHA2_256);
+        config.hashed_subpackets = vec![
+            Subpacket::regular(SubpacketData::IssuerFingerprint(key.fingerprint())),
+        ];
+        config.unhashed_subpackets = vec![Subpacket::regular(SubpacketData::Issuer(key.key_id()))];
+        let signature = config.sign(key, String::new, content).unwrap();
+        StandaloneSignature::new(signature)
+            .to_armored_string(ArmorOptions::default())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_verify_sha256sums() {
+        let (alice, alice_pub) = key("alice");
+        let (bob, bob_pub) = key("bob");
+        let (mallory, _) = key("mallory");
+
+        let keys = parse_keys(&format!("{}\n{}", alice_pub, bob_pub)).unwrap();
+        assert_eq!(keys.len(), 2);
+
+        // multiple maintainers signatures concatenated in the same `.asc`
+        let signatures = format!("{}\n{}", sign(&mallory, SUMS.as_bytes()), sign(&bob, SUMS.as_bytes()));
+        assert!(verify(SUMS.as_bytes(), &signatures, &keys).is_ok());
+        assert!(verify(SUMS.as_bytes(), &sign(&alice, SUMS.as_bytes()), &keys).is_ok());
+
+        let tampered = SUMS.replace("f247", "0000");
+        assert!(verify(tampered.as_bytes(), &signatures, &keys).is_err());
+        assert!(verify(SUMS.as_bytes(), &sign(&mallory, SUMS.as_bytes()), &keys).is_err());
+        assert!(verify(SUMS.as_bytes(), "", &keys).is_err());
+    }
+}
@@ -0,0 +1,282 @@
+use crate::plugin::Plugin;
+use crate::{LightningD, RpcError};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Answers a call to a method of a [TestPlugin] given its params
+type Method = Arc<dyn Fn(&Value) -> Result<Value, RpcError> + Send + Sync>;
+
+/// Receives the params of a notification subscribed by a [TestPlugin]
+type Subscriber = Arc<dyn Fn(&Value) + Send + Sync>;
+
+/// A plugin defined by the test, with json-rpc methods and notification subscriptions answered
+/// by closures running in the test process, to be loaded with [crate::Conf::plugins].
+///
+/// ```no_run
+/// # use lightningd::{exe_path, Conf, LightningD, TestPlugin};
+/// # use serde_json::json;
+/// let plugin = TestPlugin::new("greeter")
+///     .option("greeting", "hello", "How to greet")
+///     .method("greet", "Greet the caller", |_params| Ok(json!({"greeting": "hello"})));
+/// let mut conf = Conf::default();
+/// conf.plugins = vec![plugin];
+/// let lightningd = LightningD::with_conf(exe_path().unwrap(), &conf).unwrap();
+/// assert_eq!(lightningd.call("greet", json!({})).unwrap()["greeting"], "hello");
+/// ```
+#[derive(Clone)]
+pub struct TestPlugin {
+    name: String,
+    options: Vec<Value>,
+    methods: Vec<(String, String, Method)>,
+    subscriptions: Vec<(String, Subscriber)>,
+}
+
+impl std::fmt::Debug for TestPlugin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let methods: Vec<_> = self.methods.iter().map(|(name, _, _)| name).collect();
+        let subscriptions: Vec<_> = self.subscriptions.iter().map(|(topic, _)| topic).collect();
+        f.debug_struct("TestPlugin")
+            .field("name", &self.name)
+            .field("options", &self.options)
+            .field("methods", &methods)
+            .field("subscriptions", &subscriptions)
+            .finish()
+    }
+}
+
+impl PartialEq for TestPlugin {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.options == other.options
+            && self.methods.len() == other.methods.len()
+            && self
+                .methods
+                .iter()
+                .zip(other.methods.iter())
+                .all(|(a, b)| a.0 == b.0 && a.1 == b.1 && Arc::ptr_eq(&a.2, &b.2))
+            && self.subscriptions.len() == other.subscriptions.len()
+            && self
+                .subscriptions
+                .iter()
+                .zip(other.subscriptions.iter())
+                .all(|(a, b)| a.0 == b.0 && Arc::ptr_eq(&a.1, &b.1))
+    }
+}
+
+impl Eq for TestPlugin {}
+
+impl TestPlugin {
+    /// A plugin named `name`, without methods, options and subscriptions
+    pub fn new(name: &str) -> Self {
+        TestPlugin {
+            name: name.to_string(),
+            options: vec![],
+            methods: vec![],
+            subscriptions: vec![],
+        }
+    }
+
+    /// Name of the plugin
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Register a string option `--name`, see [LightningD::plugin_options] for its value
+    pub fn option(mut self, name: &str, default: &str, description: &str) -> Self {
+        self.options.push(json!({
+            "name": name,
+            "type": "string",
+            "default": default,
+            "description": description,
+        }));
+        self
+    }
+
+    /// Register the json-rpc method `name`, answered by `f` given the params of the call
+    pub fn method<F>(mut self, name: &str, description: &str, f: F) -> Self
+    where
+        F: Fn(&Value) -> Result<Value, RpcError> + Send + Sync + 'static,
+    {
+        self.methods
+            .push((name.to_string(), description.to_string(), Arc::new(f)));
+        self
+    }
+
+    /// Subscribe to the notifications of `topic`, passing their params to `f`
+    pub fn subscribe<F>(mut self, topic: &str, f: F) -> Self
+    where
+        F: Fn(&Value) + Send + Sync + 'static,
+    {
+        self.subscriptions.push((topic.to_string(), Arc::new(f)));
+        self
+    }
+
+    fn manifest(&self) -> Value {
+        let methods: Vec<_> = self
+            .methods
+            .iter()
+            .map(|(name, description, _)| {
+                json!({ "name": name, "usage": "", "description": description })
+            })
+            .collect();
+        let topics: Vec<_> = self.subscriptions.iter().map(|(topic, _)| topic).collect();
+        json!({
+            "options": self.options,
+            "rpcmethods": methods,
+            "subscriptions": topics,
+            "dynamic": false,
+        })
+    }
+
+    /// Start serving the plugin to a new node
+    pub(crate) fn start(&self) -> anyhow::Result<PluginHost> {
+        let options = Arc::new(Mutex::new(None));
+        let methods: HashMap<_, _> = self
+            .methods
+            .iter()
+            .map(|(name, _, f)| (name.clone(), f.clone()))
+            .collect();
+        let subscriptions: HashMap<_, _> = self.subscriptions.iter().cloned().collect();
+        let init = {
+            let options = options.clone();
+            Arc::new(move |params: &Value| {
+                *options.lock().unwrap() = Some(params["options"].clone());
+            })
+        };
+        let plugin = Plugin::start_with_init(
+            &self.name,
+            self.manifest(),
+            init,
+            Arc::new(move |method, params| {
+                if let Some(f) = methods.get(method) {
+                    f(params)
+                } else if let Some(f) = subscriptions.get(method) {
+                    f(params);
+                    Ok(json!({}))
+                } else {
+                    Err(RpcError::new(-32601, &format!("unknown method {}", method)))
+                }
+            }),
+        )?;
+        Ok(PluginHost {
+            name: self.name.clone(),
+            plugin,
+            options,
+        })
+    }
+}
+
+/// A [TestPlugin] served to a node
+#[derive(Debug)]
+pub(crate) struct PluginHost {
+    name: String,
+    plugin: Plugin,
+    options: Arc<Mutex<Option<Value>>>,
+}
+
+impl PluginHost {
+    pub(crate) fn plugin(&self) -> &Plugin {
+        &self.plugin
+    }
+}
+
+impl LightningD {
+    /// Return the option values passed on `init` to the [TestPlugin] named `name`, or `None` if
+    /// the plugin is not loaded or not yet initialized
+    pub fn plugin_options(&self, name: &str) -> Option<Value> {
+        self.plugins
+            .iter()
+            .find(|host| host.name == name)
+            .and_then(|host| host.options.lock().unwrap().clone())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::TestPlugin;
+    use crate::{exe_path, Conf, LightningD};
+    use serde_json::{json, Deserializer, Value};
+    use std::io::Write;
+    use std::process::{Command, Stdio};
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_test_plugin() {
+        let connected = Arc::new(Mutex::new(vec![]));
+        let plugin = {
+            let connected = connected.clone();
+            TestPlugin::new("greeter")
+                .option("greeting", "hello", "How to greet")
+                .method("greet", "Greet the caller", |params| {
+                    Ok(json!({ "greeting": params[0] }))
+                })
+                .subscribe("connect", move |params| {
+                    connected.lock().unwrap().push(params.clone())
+                })
+        };
+        assert_eq!(plugin, plugin.clone());
+        assert_ne!(plugin, TestPlugin::new("greeter"));
+        let host = plugin.start().unwrap();
+
+        // act as lightningd
+        let mut child = Command::new(host.plugin().path())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let mut responses = Deserializer::from_reader(stdout).into_iter::<Value>();
+        // notifications are not answered
+        let mut request = |request: Value| {
+            write!(stdin, "{}\n\n", request).unwrap();
+            match request.get("id") {
+                Some(_) => responses.next().unwrap().unwrap(),
+                None => Value::Null,
+            }
+        };
+
+        let manifest = request(json!({"jsonrpc": "2.0", "id": 1, "method": "getmanifest"}));
+        assert_eq!(manifest["result"]["rpcmethods"][0]["name"], "greet");
+        assert_eq!(manifest["result"]["subscriptions"], json!(["connect"]));
+        let params = json!({"options": {"greeting": "hi"}, "configuration": {}});
+        request(json!({"jsonrpc": "2.0", "id": 2, "method": "init", "params": params}));
+        assert_eq!(
+            *host.options.lock().unwrap(),
+            Some(json!({"greeting": "hi"}))
+        );
+        let response =
+            request(json!({"jsonrpc": "2.0", "id": 3, "method": "greet", "params": ["bob"]}));
+        assert_eq!(response["result"], json!({"greeting": "bob"}));
+        let notification = json!({"jsonrpc": "2.0", "method": "connect", "params": {"id": "02aa"}});
+        request(notification);
+        let response = request(json!({"jsonrpc": "2.0", "id": 4, "method": "unknown"}));
+        assert_eq!(response["error"]["code"], -32601);
+        assert_eq!(*connected.lock().unwrap(), vec![json!({"id": "02aa"})]);
+
+        drop(stdin);
+        assert!(child.wait().unwrap().success());
+    }
+
+    #[test]
+    fn test_plugins() {
+        let _ = env_logger::try_init();
+        let plugin = TestPlugin::new("greeter")
+            .option("greeting", "hello", "How to greet")
+            .method("greet", "Greet the caller", |_| {
+                Ok(json!({"greeting": "hello"}))
+            });
+        let conf = Conf {
+            plugins: vec![plugin],
+            ..Default::default()
+        };
+        let lightningd = LightningD::with_conf(exe_path().unwrap(), &conf).unwrap();
+        let result = lightningd.call("greet", json!({})).unwrap();
+        assert_eq!(result["greeting"], "hello");
+        assert_eq!(
+            lightningd.plugin_options("greeter").unwrap()["greeting"],
+            "hello"
+        );
+    }
+}
@@ -1,5 +1,160 @@
-#[cfg(feature = "23_05_2")]
-pub const VERSION: &str = "v23.05.2";
+// This file is shared between the library and the build script (via `include!`), so it must only
+// depend on `std`.
 
-#[cfg(all(feature = "23_05", not(feature = "23_05_2")))]
-pub const VERSION: &str = "v23.05";
\ No newline at end of file
+/// A lightning release which can be auto-downloaded by enabling its version feature
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Release {
+    /// Cargo feature selecting this release, like `23_05_2`
+    pub feature: &'static str,
+    /// Release tag, like `v23.05.2`
+    pub version: &'static str,
+    /// Ubuntu releases for which a binary tarball is published, like `22.04`
+    pub ubuntu: &'static [&'static str],
+}
+
+impl Release {
+    /// Name of the binary tarball published for the given ubuntu release
+    pub fn asset(&self, ubuntu: &str) -> String {
+        format!("clightning-{}-Ubuntu-{}.tar.xz", self.version, ubuntu)
+    }
+
+    /// Name of the bundled file containing the sha256 of the release assets
+    pub fn sha256sums_filename(&self) -> String {
+        format!("clightning-{}-SHA256SUMS", self.version)
+    }
+}
+
+const UBUNTU_18_22: &[&str] = &["18.04", "20.04", "22.04"];
+
+/// All the supported releases, from the oldest to the newest.
+///
+/// Adding a release requires a new entry here, the matching feature in `Cargo.toml` (enabling the
+/// previous release feature) and its `SHA256SUMS` file in the `sha256` directory, which must list
+/// the asset of every ubuntu release.
+pub const RELEASES: &[Release] = &[
+    Release { feature: "23_05", version: "v23.05", ubuntu: UBUNTU_18_22 },
+    Release { feature: "23_05_2", version: "v23.05.2", ubuntu: UBUNTU_18_22 },
+];
+
+/// A lightning version, as reported by `lightningd --version`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Version {
+    /// Major version, like `23` in `v23.05.2`
+    pub major: u32,
+    /// Minor version, like `5` in `v23.05.2`
+    pub minor: u32,
+    /// Patch version, like `2` in `v23.05.2`
+    pub patch: u32,
+}
+
+impl Version {
+    /// Create a version from its components
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Version { major, minor, patch }
+    }
+
+    /// Parse versions like `v23.05.2`, `v24.11` or `v24.02.2-modded`, ignoring any suffix
+    pub fn parse(s: &str) -> Option<Self> {
+        let s = s.trim().trim_start_matches('v');
+        let end = s.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(s.len());
+        let mut parts = s[..end].split('.').map(|p| p.parse::<u32>());
+        let major = parts.next()?.ok()?;
+        let minor = parts.next()?.ok()?;
+        let patch = match parts.next() {
+            Some(patch) => patch.ok()?,
+            None => 0,
+        };
+        Some(Version::new(major, minor, patch))
+    }
+}
+
+/// Returns the newest release whose feature is enabled according to `is_enabled`
+pub fn newest_enabled(is_enabled: impl Fn(&str) -> bool) -> Option<&'static Release> {
+    RELEASES.iter().rev().find(|r| is_enabled(r.feature))
+}
+
+/// Parse the content of a `SHA256SUMS` file into `(hex_hash, filename)` pairs
+pub fn parse_sha256sums(content: &str) -> Vec<(&str, &str)> {
+    content
+        .lines()
+        .filter_map(|line| {
+            let tokens: Vec<_> = line.split("  ").collect();
+            if tokens.len() == 2 {
+                Some((tokens[0], tokens[1]))
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_sha256sums, Version, RELEASES};
+    use std::path::Path;
+
+    #[test]
+    fn test_sha256sums_cover_releases() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("sha256");
+        for release in RELEASES {
+            let filename = release.sha256sums_filename();
+            let path = dir.join(&filename);
+            let content = std::fs::read_to_string(&path)
+                .unwrap_or_else(|_| panic!("{} is not bundled", filename));
+            let sums = parse_sha256sums(&content);
+            assert_eq!(sums.len(), content.lines().count(), "{} has invalid lines", filename);
+            for (hash, _) in sums.iter() {
+                assert_eq!(hash.len(), 64, "invalid hash in {}", filename);
+                assert!(hash.chars().all(|c| c.is_ascii_hexdigit()));
+            }
+            for ubuntu in release.ubuntu {
+                let asset = release.asset(ubuntu);
+                assert!(
+                    sums.iter().any(|(_, name)| *name == asset),
+                    "{} missing in {}",
+                    asset,
+                    filename
+                );
+            }
+        }
+
+        // no stray files
+        for entry in std::fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            let filename = path.file_name().unwrap().to_str().unwrap();
+            let sums = filename.strip_suffix(".asc").unwrap_or(filename);
+            assert!(
+                RELEASES.iter().any(|r| r.sha256sums_filename() == sums),
+                "{} doesn't match any release",
+                filename
+            );
+            assert!(dir.join(sums).exists(), "{} signs a missing file", filename);
+        }
+    }
+
+    #[test]
+    fn test_releases_table() {
+        for (i, release) in RELEASES.iter().enumerate() {
+            assert_eq!(
+                format!("v{}", release.feature.replace('_', ".")),
+                release.version.trim_end_matches(".0"),
+                "feature and version mismatch"
+            );
+            assert!(RELEASES[i + 1..].iter().all(|r| r.feature != release.feature));
+            assert!(!release.ubuntu.is_empty());
+            let version = Version::parse(release.version).unwrap();
+            assert!(RELEASES[i + 1..].iter().all(|r| Version::parse(r.version).unwrap() > version));
+        }
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(Version::parse("v23.05.2"), Some(Version::new(23, 5, 2)));
+        assert_eq!(Version::parse("v24.11\n"), Some(Version::new(24, 11, 0)));
+        assert_eq!(Version::parse("v24.02.2-modded"), Some(Version::new(24, 2, 2)));
+        assert_eq!(Version::parse("v24.08rc1"), Some(Version::new(24, 8, 0)));
+        assert_eq!(Version::parse("0.12.1"), Some(Version::new(0, 12, 1)));
+        assert_eq!(Version::parse("lightningd"), None);
+        assert!(Version::new(24, 11, 0) > Version::new(24, 8, 2));
+    }
+}
@@ -0,0 +1,148 @@
+use crate::payment::msat;
+use crate::LightningD;
+use serde::Deserialize;
+use serde_json::json;
+
+/// A PSBT funded by the node wallet, returned by [LightningD::fund_psbt]
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct FundedPsbt {
+    /// The base64 encoded PSBT, with the selected inputs and no outputs but the change
+    pub psbt: String,
+    /// Fee rate used to select the inputs
+    pub feerate_per_kw: u32,
+    /// Weight of the transaction once signed, including `startweight`
+    pub estimated_final_weight: u32,
+    /// Amount in excess of the requested one and of the fees, added to the change if any
+    #[serde(deserialize_with = "msat")]
+    pub excess_msat: u64,
+}
+
+impl LightningD {
+    /// Send `amount_sat` from the wallet to the `destination` address, or all the funds if `None`,
+    /// returning the txid
+    pub fn withdraw(&self, destination: &str, amount_sat: Option<u64>) -> anyhow::Result<String> {
+        let amount = match amount_sat {
+            Some(amount_sat) => json!(amount_sat),
+            None => json!("all"),
+        };
+        let result = self.call("withdraw", json!([destination, amount]))?;
+        txid(&result)
+    }
+
+    /// Select wallet inputs funding `amount_sat` at `feerate`, like `normal` or `253perkw`,
+    /// reserving them. The outputs are left to the caller.
+    pub fn fund_psbt(&self, amount_sat: u64, feerate: &str) -> anyhow::Result<FundedPsbt> {
+        let result = self.call("fundpsbt", json!([amount_sat, feerate, 0]))?;
+        Ok(serde_json::from_value(result)?)
+    }
+
+    /// Sign the wallet inputs of the base64 encoded `psbt`
+    pub fn sign_psbt(&self, psbt: &str) -> anyhow::Result<String> {
+        let result = self.call("signpsbt", json!([psbt]))?;
+        result["signed_psbt"]
+            .as_str()
+            .map(ToString::to_string)
+            .ok_or_else(|| anyhow::anyhow!("missing signed_psbt in {}", result))
+    }
+
+    /// Finalize and broadcast the signed `psbt`, returning the txid
+    pub fn send_psbt(&self, psbt: &str) -> anyhow::Result<String> {
+        let result = self.call("sendpsbt", json!([psbt]))?;
+        txid(&result)
+    }
+}
+
+fn txid(result: &serde_json::Value) -> anyhow::Result<String> {
+    result["txid"]
+        .as_str()
+        .map(ToString::to_string)
+        .ok_or_else(|| anyhow::anyhow!("missing txid in {}", result))
+}
+
+#[cfg(feature = "bitcoin_backend")]
+impl LightningD {
+    /// Send `amount_sat` to a new address of the wallet and confirm it, returning once lightningd
+    /// lists the confirmed output. Requires a node started with [crate::Conf::fake_chain].
+    pub fn fund_wallet(&self, amount_sat: u64) -> anyhow::Result<bitcoin::Txid> {
+        self.fund_wallet_utxos(&[amount_sat])
+    }
+
+    /// Like [LightningD::fund_wallet], creating an output for every amount, all in the same
+    /// transaction
+    pub fn fund_wallet_utxos(&self, amounts_sat: &[u64]) -> anyhow::Result<bitcoin::Txid> {
+        let chain = self
+            .fake_chain()
+            .ok_or_else(|| anyhow::anyhow!("funding the wallet requires Conf::fake_chain"))?;
+        let mut outputs = vec![];
+        for amount_sat in amounts_sat {
+            outputs.push(bitcoin::TxOut {
+                value: bitcoin::Amount::from_sat(*amount_sat),
+                script_pubkey: crate::chain::new_script_pubkey(self)?,
+            });
+        }
+        let txid = chain.send(outputs);
+        chain.mine(1);
+
+        let mut result = json!(null);
+        crate::poll_until(crate::SYNC_TIMEOUT, || {
+            result = self.call("listfunds", json!([]))?;
+            let confirmed = result["outputs"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter(|o| o["txid"] == txid.to_string() && o["status"] == "confirmed")
+                .count();
+            Ok(Some(txid).filter(|_| confirmed == amounts_sat.len()))
+        })?
+        .ok_or_else(|| anyhow::anyhow!("outputs of {} not confirmed in {}", txid, result))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FundedPsbt;
+    use serde_json::json;
+
+    #[test]
+    fn test_deserialize_funded_psbt() {
+        let funded = json!({
+            "psbt": "cHNidP8BAAoCAAAAAAAAAAAAAA==",
+            "feerate_per_kw": 253,
+            "estimated_final_weight": 444,
+            "excess_msat": "1000msat",
+            "change_outnum": 0,
+            "reservations": [],
+        });
+        let funded: FundedPsbt = serde_json::from_value(funded).unwrap();
+        assert_eq!(funded.excess_msat, 1000);
+    }
+
+    #[cfg(feature = "bitcoin_backend")]
+    #[test]
+    fn test_fund_wallet() {
+        use crate::{exe_path, Conf, FakeChain, LightningD};
+
+        let _ = env_logger::try_init();
+        let chain = FakeChain::new();
+        let conf = Conf {
+            fake_chain: Some(chain.clone()),
+            ..Default::default()
+        };
+        let alice = LightningD::with_conf(exe_path().unwrap(), &conf).unwrap();
+        let bob = LightningD::with_conf(exe_path().unwrap(), &conf).unwrap();
+        alice.fund_wallet_utxos(&[100_000, 200_000]).unwrap();
+
+        let address = bob.call("newaddr", json!([])).unwrap()["bech32"].clone();
+        let txid = alice
+            .withdraw(address.as_str().unwrap(), Some(50_000))
+            .unwrap();
+        assert!(chain
+            .mempool()
+            .iter()
+            .any(|tx| tx.compute_txid().to_string() == txid));
+
+        let funded = alice.fund_psbt(100_000, "normal").unwrap();
+        let signed = alice.sign_psbt(&funded.psbt).unwrap();
+        assert!(alice.send_psbt(&signed).is_ok());
+    }
+}